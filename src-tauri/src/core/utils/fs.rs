@@ -12,10 +12,38 @@ pub fn ensure_parent_exists(file_path: &PathBuf) -> Result<(), String> {
 }
 
 pub fn write_atomically(file_path: &PathBuf, buf: serde_json::Value) -> Result<(), String> {
+    write_string_atomically(file_path, &buf.to_string())
+}
+
+pub fn write_string_atomically(file_path: &PathBuf, buf: &str) -> Result<(), String> {
     ensure_parent_exists(&file_path)?;
     let af = AtomicFile::new(&file_path, OverwriteBehavior::AllowOverwrite);
-    match af.write(|f| f.write_all(&buf.to_string().into_bytes())) {
+    match af.write(|f| f.write_all(buf.as_bytes())) {
         Ok(_) => Ok(()),
         Err(e) => Err(e.to_string()),
     }
 }
+
+// Recursively copy a directory and everything in it
+pub fn copy_dir(from: &PathBuf, to: &PathBuf) -> Result<(), String> {
+    if let Err(e) = std::fs::create_dir_all(to) {
+        throw!("Error creating folder {}: {}", to.display(), e);
+    }
+    let entries = match std::fs::read_dir(from) {
+        Ok(entries) => entries,
+        Err(e) => throw!("Error reading folder {}: {}", from.display(), e),
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => throw!("Error reading folder {}: {}", from.display(), e),
+        };
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if let Err(e) = std::fs::copy(entry.path(), &target) {
+            throw!("Error copying {}: {}", entry.path().display(), e);
+        }
+    }
+    Ok(())
+}
@@ -1,2 +1,3 @@
 pub mod fs;
-pub mod json;
\ No newline at end of file
+pub mod json;
+pub mod time;
\ No newline at end of file
@@ -0,0 +1,52 @@
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Milliseconds since the unix epoch, matching JS `Date.now()`
+pub fn now_millis() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as u64,
+        Err(_) => 0,
+    }
+}
+
+// Last modification time of a file, or the current time if it can't be read
+pub fn file_modified_millis(path: &PathBuf) -> u64 {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified());
+    match modified.map(|t| t.duration_since(UNIX_EPOCH)) {
+        Ok(Ok(d)) => d.as_millis() as u64,
+        _ => now_millis(),
+    }
+}
+
+// Format a millisecond timestamp as an RFC 3339 date, e.g. `2022-12-01T10:30:00.000Z`
+pub fn to_rfc3339(millis: u64) -> String {
+    match Utc.timestamp_millis_opt(millis as i64).single() {
+        Some(date) => date.to_rfc3339_opts(SecondsFormat::Millis, true),
+        None => String::new(),
+    }
+}
+
+pub fn from_rfc3339(date: &str) -> Option<u64> {
+    match DateTime::parse_from_rfc3339(date.trim()) {
+        Ok(date) => u64::try_from(date.timestamp_millis()).ok(),
+        Err(_) => None,
+    }
+}
+
+// A millisecond timestamp as a UTC date that sorts and can go in a file name,
+// e.g. `20221201T103000.000Z`
+pub fn to_file_date(millis: u64) -> String {
+    match Utc.timestamp_millis_opt(millis as i64).single() {
+        Some(date) => date.format("%Y%m%dT%H%M%S%.3fZ").to_string(),
+        None => String::new(),
+    }
+}
+
+pub fn from_file_date(date: &str) -> Option<u64> {
+    let date = format!("{} +0000", date.trim());
+    match DateTime::parse_from_str(&date, "%Y%m%dT%H%M%S%.3fZ %z") {
+        Ok(date) => u64::try_from(date.timestamp_millis()).ok(),
+        Err(_) => None,
+    }
+}
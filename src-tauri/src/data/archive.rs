@@ -0,0 +1,376 @@
+use crate::core::utils::fs::ensure_parent_exists;
+use crate::core::utils::json::to_json;
+use crate::core::utils::time::now_millis;
+use crate::data::backend::Backend;
+use crate::data::import::{ImportReport, ImportedNote, ATTACHMENTS_DIR, VAULT_ATTACHMENT};
+use crate::data::markdown::{self, file_names};
+use crate::data::note::Note;
+use crate::data::notebooks::{Notebook, Notebooks};
+use crate::data::{Data, NoteFile, Notes, KV};
+
+use atomicwrites::{AtomicFile, OverwriteBehavior};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{Read, Seek, Write};
+use std::path::{Component, PathBuf};
+use tauri::State;
+use uuid::Uuid;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+pub const ARCHIVE_FORMAT_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const NOTES_DIR: &str = "notes";
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ManifestNote {
+    pub uuid: Uuid,
+    // Where the note is in the archive, e.g. `notes/groceries.md`
+    pub path: String,
+    pub title: String,
+    pub created_at: u64,
+    pub modified_at: u64,
+    pub locked: bool,
+}
+
+// `manifest.json`, listing what the archive holds. Everything else about a
+// note is in its markdown front matter
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub exported_at: u64,
+    pub notes: Vec<ManifestNote>,
+    pub notebooks: Vec<Notebook>,
+    // Paths in the archive, e.g. `attachments/photo.png`
+    pub attachments: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ArchiveSummary {
+    pub path: PathBuf,
+    pub notes: usize,
+    pub notebooks: usize,
+    pub attachments: usize,
+}
+
+// What to do with a note in the archive whose uuid is already taken
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    // Keep the note already here
+    Skip,
+    // Replace it with the one in the archive
+    Overwrite,
+    // Import the archived note as a new one next to it
+    Duplicate,
+}
+
+// Every file under `dir`, relative to it
+pub fn list_files(
+    dir: &PathBuf,
+    relative: &PathBuf,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let entries = match std::fs::read_dir(dir.join(relative)) {
+        Ok(entries) => entries,
+        Err(e) => throw!("Error reading folder {}: {}", dir.display(), e),
+    };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+    for path in paths {
+        let name = match path.file_name() {
+            Some(name) => relative.join(name),
+            None => continue,
+        };
+        match path.is_dir() {
+            true => list_files(dir, &name, files)?,
+            false => files.push(name),
+        }
+    }
+    Ok(())
+}
+
+// A path inside the archive, always with forward slashes
+fn archive_path(relative: &PathBuf) -> String {
+    let parts: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    parts.join("/")
+}
+
+// Paths that would leave the folder they are extracted to are refused
+pub fn safe_path(path: &str) -> Option<PathBuf> {
+    let relative = PathBuf::from(path);
+    let is_plain = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    match is_plain && !path.is_empty() {
+        true => Some(relative),
+        false => None,
+    }
+}
+
+fn add_file<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    name: &str,
+    bytes: &[u8],
+) -> Result<(), String> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    if let Err(e) = zip.start_file(name, options) {
+        throw!("Error adding {} to the archive: {}", name, e);
+    }
+    match zip.write_all(bytes) {
+        Ok(_) => Ok(()),
+        Err(e) => throw!("Error adding {} to the archive: {}", name, e),
+    }
+}
+
+fn read_file<R: Read + Seek>(zip: &mut ZipArchive<R>, name: &str) -> Result<Vec<u8>, String> {
+    let mut file = match zip.by_name(name) {
+        Ok(file) => file,
+        Err(e) => throw!("Could not find {} in the archive: {}", name, e),
+    };
+    let mut bytes = vec![];
+    match file.read_to_end(&mut bytes) {
+        Ok(_) => Ok(bytes),
+        Err(e) => throw!("Could not read {} from the archive: {}", name, e),
+    }
+}
+
+// Write every note as markdown, the notebooks and the attachments to a zip at
+// `path`. The archive only replaces an existing file once it is complete
+pub fn write_archive<B: Backend>(
+    path: &PathBuf,
+    notes: &Notes<B>,
+    notebooks: &Notebooks,
+) -> Result<ArchiveSummary, String> {
+    let mut entries: Vec<NoteFile> = notes.get_all();
+    entries.sort_by_key(|note| (note.content.created_at, note.uuid));
+    let titled: Vec<(Uuid, Note)> = entries
+        .iter()
+        .filter_map(|note| note.uuid.map(|uuid| (uuid, note.content.to_owned())))
+        .collect();
+    let names = file_names(&titled, "md");
+
+    let attachments_dir = notes.data_path.join(ATTACHMENTS_DIR);
+    let mut attachments = vec![];
+    if attachments_dir.is_dir() {
+        list_files(&attachments_dir, &PathBuf::new(), &mut attachments)?;
+    }
+
+    let mut manifest = Manifest {
+        format_version: ARCHIVE_FORMAT_VERSION,
+        exported_at: now_millis(),
+        notes: vec![],
+        notebooks: notebooks.list(),
+        attachments: vec![],
+    };
+    ensure_parent_exists(path)?;
+    let file = AtomicFile::new(path, OverwriteBehavior::AllowOverwrite);
+    let written = file.write(|file| {
+        let mut zip = ZipWriter::new(file);
+        for note in entries.iter() {
+            let uuid = match note.uuid {
+                Some(uuid) => uuid,
+                None => continue,
+            };
+            let name = format!("{}/{}", NOTES_DIR, names[&uuid]);
+            add_file(&mut zip, &name, markdown::render(note)?.as_bytes())?;
+            manifest.notes.push(ManifestNote {
+                uuid,
+                path: name,
+                title: note.content.title.to_owned(),
+                created_at: note.content.created_at,
+                modified_at: note.content.modified_at,
+                locked: note.locked.is_some(),
+            });
+        }
+        for relative in attachments.iter() {
+            let name = format!("{}/{}", ATTACHMENTS_DIR, archive_path(relative));
+            let bytes = match std::fs::read(attachments_dir.join(relative)) {
+                Ok(bytes) => bytes,
+                Err(e) => throw!("Error reading {}: {}", relative.display(), e),
+            };
+            add_file(&mut zip, &name, &bytes)?;
+            manifest.attachments.push(name);
+        }
+        add_file(
+            &mut zip,
+            MANIFEST_FILE,
+            to_json(&manifest)?.to_string().as_bytes(),
+        )?;
+        match zip.finish() {
+            Ok(_) => Ok(()),
+            Err(e) => throw!("Error writing the archive: {}", e),
+        }
+    });
+    if let Err(e) = written {
+        throw!("Could not export to {}: {}", path.display(), e);
+    }
+
+    Ok(ArchiveSummary {
+        path: path.to_path_buf(),
+        notes: manifest.notes.len(),
+        notebooks: manifest.notebooks.len(),
+        attachments: manifest.attachments.len(),
+    })
+}
+
+// Restore an archive written by `write_archive`. Notes keep their uuids, so
+// links between them still work; `on_conflict` decides what happens to the
+// ones already here. All notes are saved in one batch
+pub fn read_archive<B: Backend>(
+    path: &PathBuf,
+    on_conflict: OnConflict,
+    notes: &mut Notes<B>,
+    notebooks: &mut Notebooks,
+) -> Result<ImportReport, String> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => throw!("Could not open {}: {}", path.display(), e),
+    };
+    let mut zip = match ZipArchive::new(file) {
+        Ok(zip) => zip,
+        Err(e) => throw!("{} is not a zip archive: {}", path.display(), e),
+    };
+    let manifest: Manifest = match serde_json::from_slice(&read_file(&mut zip, MANIFEST_FILE)?) {
+        Ok(manifest) => manifest,
+        Err(e) => throw!("Could not parse the archive manifest: {}", e),
+    };
+    if manifest.format_version > ARCHIVE_FORMAT_VERSION {
+        throw!(
+            "The archive is from a newer version of the app (format {})",
+            manifest.format_version
+        );
+    }
+
+    let mut report = ImportReport {
+        notebooks: notebooks.restore_many(&manifest.notebooks)?,
+        ..Default::default()
+    };
+
+    let mut batch = vec![];
+    for entry in manifest.notes.iter() {
+        let source = PathBuf::from(&entry.path);
+        let text = match read_file(&mut zip, &entry.path).map(String::from_utf8) {
+            Ok(Ok(text)) => text,
+            Ok(Err(e)) => {
+                report.fail(&source, &e.to_string());
+                continue;
+            }
+            Err(e) => {
+                report.fail(&source, &e);
+                continue;
+            }
+        };
+        let mut note = match markdown::parse(&text, &source, entry.modified_at) {
+            Ok(note) => note,
+            Err(e) => {
+                report.fail(&source, &e);
+                continue;
+            }
+        };
+        let uuid = note.uuid.unwrap_or(entry.uuid);
+        if let Some(parent) = note.content.parent {
+            if !notebooks.has(&parent) {
+                note.content.parent = None;
+            }
+        }
+
+        let existing = notes.entries.get(&uuid);
+        note.uuid = Some(uuid);
+        note.file_path = notes.data_path.join(format!("{}.json", uuid));
+        match (existing, on_conflict) {
+            (None, _) => {}
+            (Some(_), OnConflict::Skip) => {
+                report.skipped.push(ImportedNote {
+                    source,
+                    uuid,
+                    title: note.content.title,
+                });
+                continue;
+            }
+            (Some(existing), OnConflict::Overwrite) => {
+                note.file_path = existing.file_path.to_owned();
+            }
+            (Some(_), OnConflict::Duplicate) => {
+                let copy = NoteFile::new(&notes.data_path, &note.content);
+                note.uuid = copy.uuid;
+                note.file_path = copy.file_path;
+            }
+        }
+        batch.push((source, note));
+    }
+
+    let mut attachments = 0;
+    for name in manifest.attachments.iter() {
+        let source = PathBuf::from(name);
+        if notes.backend.encrypted() {
+            report.warn(&source, VAULT_ATTACHMENT);
+            continue;
+        }
+        let target = match name
+            .strip_prefix(&format!("{}/", ATTACHMENTS_DIR))
+            .and_then(safe_path)
+        {
+            Some(relative) => notes.data_path.join(ATTACHMENTS_DIR).join(relative),
+            None => {
+                report.warn(&source, "Not an attachment path, skipped");
+                continue;
+            }
+        };
+        let bytes = match read_file(&mut zip, name) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                report.warn(&source, &e);
+                continue;
+            }
+        };
+        match std::fs::read(&target) {
+            Ok(existing) if existing == bytes => continue,
+            Ok(_) if on_conflict != OnConflict::Overwrite => {
+                report.warn(&source, "A different file with this name exists, kept it");
+                continue;
+            }
+            _ => {}
+        }
+        ensure_parent_exists(&target)?;
+        match std::fs::write(&target, &bytes) {
+            Ok(_) => attachments += 1,
+            Err(e) => report.warn(&source, &e.to_string()),
+        }
+    }
+    report.attachments = attachments;
+
+    let files: Vec<NoteFile> = batch.iter().map(|(_, note)| note.to_owned()).collect();
+    notes.put_many(&files)?;
+    for (source, note) in batch {
+        report.imported.push(ImportedNote {
+            source,
+            uuid: note.uuid.unwrap_or_default(),
+            title: note.content.title,
+        });
+    }
+    Ok(report)
+}
+
+// Back up every note, notebook and attachment to a zip at `path`
+#[tauri::command]
+pub fn export_archive(path: PathBuf, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.export_archive(&path)?)
+}
+
+#[tauri::command]
+pub fn import_archive(
+    path: PathBuf,
+    on_conflict: Option<OnConflict>,
+    data: State<'_, Data>,
+) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    let on_conflict = on_conflict.unwrap_or(OnConflict::Skip);
+
+    to_json(&cache.import_archive(&path, on_conflict)?)
+}
@@ -0,0 +1,128 @@
+use crate::data::backend::{note_uuid, Backend};
+use crate::data::migrations::{migrate_data_dir, MigrationReport};
+use crate::data::settings::StorageFormat;
+use crate::data::{is_note_path, NoteFile};
+
+use std::collections::HashMap;
+use std::fs::read_dir;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// One file per note in the data directory, as JSON or Markdown
+#[derive(Debug)]
+pub struct FileBackend {
+    pub data_path: PathBuf,
+    pub format: StorageFormat,
+    // Markdown notes are named after their title, so remember where each one lives
+    paths: HashMap<Uuid, PathBuf>,
+}
+
+impl FileBackend {
+    pub fn new(data_path: &PathBuf, format: StorageFormat) -> Self {
+        if let Err(e) = std::fs::create_dir_all(data_path) {
+            eprintln!("Error creating data directory: {}", e);
+        }
+        Self {
+            data_path: data_path.to_path_buf(),
+            format,
+            paths: HashMap::new(),
+        }
+    }
+
+    // Load every note file in the data directory with `load`
+    fn load_all(
+        &mut self,
+        load: fn(&PathBuf) -> Result<NoteFile, String>,
+    ) -> Result<Vec<NoteFile>, String> {
+        let entries = match read_dir(&self.data_path) {
+            Ok(entries) => entries,
+            Err(e) => throw!("Error reading data directory: {}", e),
+        };
+        // Notes may have moved since, e.g. when a backup was restored
+        self.paths.clear();
+        let mut notes = vec![];
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !is_note_path(&path) {
+                continue;
+            }
+            match load(&path).and_then(|note| Ok((note_uuid(&note)?, note))) {
+                Ok((uuid, note)) => {
+                    self.paths.insert(uuid, path);
+                    notes.push(note);
+                }
+                Err(e) => eprintln!("Error loading {}: {}", path.display(), e),
+            }
+        }
+        Ok(notes)
+    }
+}
+
+impl Backend for FileBackend {
+    fn list(&mut self) -> Result<Vec<NoteFile>, String> {
+        self.load_all(NoteFile::load)
+    }
+
+    fn list_headers(&mut self) -> Result<Vec<NoteFile>, String> {
+        self.load_all(NoteFile::load_header)
+    }
+
+    fn load(&self, uuid: &Uuid) -> Result<Option<NoteFile>, String> {
+        match self.paths.get(uuid) {
+            Some(path) => Ok(Some(NoteFile::load(path)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&mut self, note: &NoteFile) -> Result<NoteFile, String> {
+        let uuid = note_uuid(note)?;
+        let mut note = note.to_owned();
+        // Notes new to this directory start out at `<uuid>.json`; `save_as`
+        // then picks the final name for the configured format
+        note.file_path = match self.paths.get(&uuid) {
+            Some(path) => path.to_path_buf(),
+            None => self.data_path.join(format!("{}.json", uuid)),
+        };
+
+        let content = note.content.to_owned();
+        let saved = note.save_as(&content, self.format)?;
+        self.paths.insert(uuid, saved.file_path.to_path_buf());
+        Ok(saved)
+    }
+
+    fn delete(&mut self, uuid: &Uuid) -> Result<(), String> {
+        if let Some(path) = self.paths.remove(uuid) {
+            if let Err(e) = std::fs::remove_file(&path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    throw!("Error removing {}: {}", path.display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn migrate(&mut self, backup_root: &PathBuf) -> Result<MigrationReport, String> {
+        migrate_data_dir(&self.data_path, backup_root)
+    }
+
+    fn watch_dir(&self) -> Option<PathBuf> {
+        Some(self.data_path.to_path_buf())
+    }
+
+    fn load_path(&mut self, path: &PathBuf) -> Result<Option<NoteFile>, String> {
+        // Notes sit directly in the data directory; atomic writes stage
+        // their temporary files in a subdirectory
+        if path.parent() != Some(self.data_path.as_path()) || !is_note_path(path) {
+            return Ok(None);
+        }
+        let note = NoteFile::load(path)?;
+        self.paths.insert(note_uuid(&note)?, path.to_path_buf());
+        Ok(Some(note))
+    }
+
+    fn forget_path(&mut self, path: &PathBuf) -> Option<Uuid> {
+        let uuid = *self.paths.iter().find(|(_, p)| *p == path)?.0;
+        self.paths.remove(&uuid);
+        Some(uuid)
+    }
+}
@@ -0,0 +1,38 @@
+use crate::data::backend::{note_uuid, Backend};
+use crate::data::NoteFile;
+
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// Keeps notes in memory only. Nothing survives a restart, which makes it
+// handy for tests and for trying the app out
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    notes: HashMap<Uuid, NoteFile>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn list(&mut self) -> Result<Vec<NoteFile>, String> {
+        Ok(self.notes.values().map(|n| n.to_owned()).collect())
+    }
+
+    fn load(&self, uuid: &Uuid) -> Result<Option<NoteFile>, String> {
+        Ok(self.notes.get(uuid).map(|n| n.to_owned()))
+    }
+
+    fn save(&mut self, note: &NoteFile) -> Result<NoteFile, String> {
+        self.notes.insert(note_uuid(note)?, note.to_owned());
+        Ok(note.to_owned())
+    }
+
+    fn delete(&mut self, uuid: &Uuid) -> Result<(), String> {
+        self.notes.remove(uuid);
+        Ok(())
+    }
+}
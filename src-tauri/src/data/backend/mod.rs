@@ -0,0 +1,165 @@
+pub mod files;
+pub mod memory;
+pub mod sqlite;
+pub mod vault;
+
+use crate::data::migrations::MigrationReport;
+use crate::data::search::SearchHit;
+use crate::data::NoteFile;
+
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// Where notes are persisted. `Notes` keeps every note in memory, reading them
+// from the backend at startup and writing each change through to it
+pub trait Backend: Send + std::fmt::Debug {
+    // Every stored note
+    fn list(&mut self) -> Result<Vec<NoteFile>, String>;
+    // Every stored note with an empty body, which is all startup needs.
+    // Backends that can skip reading the bodies do so
+    fn list_headers(&mut self) -> Result<Vec<NoteFile>, String> {
+        let mut notes = self.list()?;
+        for note in notes.iter_mut() {
+            note.content.body = String::new();
+        }
+        Ok(notes)
+    }
+    fn load(&self, uuid: &Uuid) -> Result<Option<NoteFile>, String>;
+    // Write a note, returning it as stored (file backends may rename it)
+    fn save(&mut self, note: &NoteFile) -> Result<NoteFile, String>;
+    fn delete(&mut self, uuid: &Uuid) -> Result<(), String>;
+    // Save several notes at once. Backends that can do so write them atomically
+    fn save_many(&mut self, notes: &[NoteFile]) -> Result<Vec<NoteFile>, String> {
+        notes.iter().map(|note| self.save(note)).collect()
+    }
+    // Whether the backend keeps its own full-text index. Otherwise `Notes`
+    // searches with the in-memory `SearchIndex`
+    fn full_text(&self) -> bool {
+        false
+    }
+    fn search(&self, _query: &str, _limit: usize) -> Result<Vec<SearchHit>, String> {
+        Ok(vec![])
+    }
+    // Upgrade stored notes to the current format, run once at startup
+    fn migrate(&mut self, _backup_root: &PathBuf) -> Result<MigrationReport, String> {
+        Ok(MigrationReport::default())
+    }
+    // The form a note is stored in, for copies kept outside the backend like the trash
+    fn seal(&self, note: &NoteFile) -> Result<NoteFile, String> {
+        Ok(note.to_owned())
+    }
+    fn unseal(&self, note: NoteFile) -> Result<NoteFile, String> {
+        Ok(note)
+    }
+    // Whether notes are stored encrypted, so nothing else may be written as plaintext
+    fn encrypted(&self) -> bool {
+        false
+    }
+    // Drop what overwritten and deleted notes leave behind in storage, e.g.
+    // so plaintext doesn't linger once the vault has encrypted every note
+    fn compact(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    // Copy whatever the backend keeps outside the data directory into `dir`, for a backup
+    fn backup_to(&self, _dir: &PathBuf) -> Result<(), String> {
+        Ok(())
+    }
+    // Put back what `backup_to` copied into `dir`
+    fn restore_from(&mut self, _dir: &PathBuf) -> Result<(), String> {
+        Ok(())
+    }
+    // The directory holding a file per note, watched for changes made outside the app
+    fn watch_dir(&self) -> Option<PathBuf> {
+        None
+    }
+    // Read a note back from a file in `watch_dir` that changed on disk.
+    // Files that don't hold a note give `None`
+    fn load_path(&mut self, _path: &PathBuf) -> Result<Option<NoteFile>, String> {
+        Ok(None)
+    }
+    // The note stored at a path in `watch_dir` that is gone
+    fn forget_path(&mut self, _path: &PathBuf) -> Option<Uuid> {
+        None
+    }
+}
+
+impl<T: Backend + ?Sized> Backend for Box<T> {
+    fn list(&mut self) -> Result<Vec<NoteFile>, String> {
+        (**self).list()
+    }
+
+    fn list_headers(&mut self) -> Result<Vec<NoteFile>, String> {
+        (**self).list_headers()
+    }
+
+    fn load(&self, uuid: &Uuid) -> Result<Option<NoteFile>, String> {
+        (**self).load(uuid)
+    }
+
+    fn save(&mut self, note: &NoteFile) -> Result<NoteFile, String> {
+        (**self).save(note)
+    }
+
+    fn delete(&mut self, uuid: &Uuid) -> Result<(), String> {
+        (**self).delete(uuid)
+    }
+
+    fn save_many(&mut self, notes: &[NoteFile]) -> Result<Vec<NoteFile>, String> {
+        (**self).save_many(notes)
+    }
+
+    fn full_text(&self) -> bool {
+        (**self).full_text()
+    }
+
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, String> {
+        (**self).search(query, limit)
+    }
+
+    fn migrate(&mut self, backup_root: &PathBuf) -> Result<MigrationReport, String> {
+        (**self).migrate(backup_root)
+    }
+
+    fn seal(&self, note: &NoteFile) -> Result<NoteFile, String> {
+        (**self).seal(note)
+    }
+
+    fn unseal(&self, note: NoteFile) -> Result<NoteFile, String> {
+        (**self).unseal(note)
+    }
+
+    fn encrypted(&self) -> bool {
+        (**self).encrypted()
+    }
+
+    fn compact(&mut self) -> Result<(), String> {
+        (**self).compact()
+    }
+
+    fn backup_to(&self, dir: &PathBuf) -> Result<(), String> {
+        (**self).backup_to(dir)
+    }
+
+    fn restore_from(&mut self, dir: &PathBuf) -> Result<(), String> {
+        (**self).restore_from(dir)
+    }
+
+    fn watch_dir(&self) -> Option<PathBuf> {
+        (**self).watch_dir()
+    }
+
+    fn load_path(&mut self, path: &PathBuf) -> Result<Option<NoteFile>, String> {
+        (**self).load_path(path)
+    }
+
+    fn forget_path(&mut self, path: &PathBuf) -> Option<Uuid> {
+        (**self).forget_path(path)
+    }
+}
+
+pub fn note_uuid(note: &NoteFile) -> Result<Uuid, String> {
+    match note.uuid {
+        Some(uuid) => Ok(uuid),
+        None => throw!("Cannot store a note without a uuid"),
+    }
+}
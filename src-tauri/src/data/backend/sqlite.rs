@@ -0,0 +1,403 @@
+use crate::core::utils::fs::ensure_parent_exists;
+use crate::core::utils::json::to_json;
+use crate::data::backend::files::FileBackend;
+use crate::data::backend::{note_uuid, Backend};
+use crate::data::migrations::{self, Envelope, CURRENT_FORMAT_VERSION};
+use crate::data::search::{escape_html, tokenize, SearchHit, TITLE_WEIGHT};
+use crate::data::settings::StorageFormat;
+use crate::data::NoteFile;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// Schema changes in the order they were made. The number applied so far is
+// kept in `PRAGMA user_version`
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS notes (
+        uuid TEXT PRIMARY KEY NOT NULL,
+        data TEXT NOT NULL,
+        modified_at INTEGER NOT NULL
+    );",
+    "CREATE VIRTUAL TABLE notes_fts USING fts5(
+        uuid UNINDEXED,
+        title,
+        body,
+        tokenize = 'unicode61 remove_diacritics 2'
+    );
+    INSERT INTO notes_fts (uuid, title, body)
+        SELECT uuid, json_extract(data, '$.data.content.title'), json_extract(data, '$.data.content.body')
+        FROM notes;
+    CREATE TABLE meta (
+        key TEXT PRIMARY KEY NOT NULL,
+        value TEXT NOT NULL
+    );",
+    // Key the FTS rows by an explicit integer id, which unlike the implicit
+    // rowid survives VACUUM, instead of looking them up by uuid
+    "CREATE TABLE notes_by_id (
+        id INTEGER PRIMARY KEY,
+        uuid TEXT UNIQUE NOT NULL,
+        data TEXT NOT NULL,
+        modified_at INTEGER NOT NULL
+    );
+    INSERT INTO notes_by_id (uuid, data, modified_at)
+        SELECT uuid, data, modified_at FROM notes;
+    DROP TABLE notes;
+    ALTER TABLE notes_by_id RENAME TO notes;
+    DROP TABLE notes_fts;
+    CREATE VIRTUAL TABLE notes_fts USING fts5(
+        title,
+        body,
+        tokenize = 'unicode61 remove_diacritics 2'
+    );
+    INSERT INTO notes_fts (rowid, title, body)
+        SELECT id, json_extract(data, '$.data.content.title'), json_extract(data, '$.data.content.body')
+        FROM notes;",
+];
+
+// The database's name in the app directory
+pub const DATABASE_FILE: &str = "notes.sqlite3";
+
+// Set in `meta` once the JSON data directory has been imported
+const IMPORTED_KEY: &str = "imported_data_dir";
+
+// Marks around matches in FTS5 snippets, swapped for `<mark>` once the text is escaped
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+// Every note in a single SQLite database file. Rows hold the same
+// versioned envelope the JSON files do, and an FTS5 table mirrors the
+// title and body for search, in rows sharing the note's id
+#[derive(Debug)]
+pub struct SqliteBackend {
+    pub db_path: PathBuf,
+    conn: Connection,
+}
+
+fn connect(db_path: &PathBuf) -> Result<Connection, String> {
+    ensure_parent_exists(db_path)?;
+    let conn = match Connection::open(db_path) {
+        Ok(conn) => conn,
+        Err(e) => throw!("Error opening {}: {}", db_path.display(), e),
+    };
+    if let Err(e) = conn.execute_batch("PRAGMA journal_mode = WAL;") {
+        throw!("Error opening {}: {}", db_path.display(), e);
+    }
+    Ok(conn)
+}
+
+impl SqliteBackend {
+    pub fn open(db_path: &PathBuf) -> Result<Self, String> {
+        let mut backend = Self {
+            db_path: db_path.to_path_buf(),
+            conn: connect(db_path)?,
+        };
+        backend.upgrade_schema()?;
+        Ok(backend)
+    }
+
+    fn upgrade_schema(&mut self) -> Result<(), String> {
+        let version: usize = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        for (i, change) in SCHEMA.iter().enumerate().skip(version) {
+            let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+            if let Err(e) = tx.execute_batch(change) {
+                throw!("Error upgrading notes database to version {}: {}", i + 1, e);
+            }
+            tx.pragma_update(None, "user_version", i + 1)
+                .map_err(|e| e.to_string())?;
+            tx.commit().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    // Copy every note in the file-based data directory into the database.
+    // This only ever happens once; the files are left where they are
+    pub fn import_data_dir(&mut self, data_path: &PathBuf) -> Result<usize, String> {
+        let imported: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![IMPORTED_KEY],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if imported.is_some() {
+            return Ok(0);
+        }
+        let notes = match data_path.is_dir() {
+            true => FileBackend::new(data_path, StorageFormat::Json).list()?,
+            false => vec![],
+        };
+
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        for note in notes.iter() {
+            write_note(&tx, note)?;
+        }
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)",
+            params![IMPORTED_KEY, data_path.to_string_lossy()],
+        )
+        .map_err(|e| e.to_string())?;
+        if let Err(e) = tx.commit() {
+            throw!("Error importing notes: {}", e);
+        }
+        Ok(notes.len())
+    }
+
+    fn parse(&self, data: &str) -> Result<NoteFile, String> {
+        let value: Value = match serde_json::from_str(data) {
+            Ok(value) => value,
+            Err(e) => throw!("Could not parse stored note: {}", e),
+        };
+        let (data, _) = migrations::upgrade(value, &self.db_path)?;
+        match serde_json::from_value(data) {
+            Ok(note) => Ok(note),
+            Err(e) => throw!("Could not parse stored note: {}", e),
+        }
+    }
+}
+
+fn write_note(conn: &Connection, note: &NoteFile) -> Result<(), String> {
+    let uuid = note_uuid(note)?.to_string();
+    let envelope = Envelope {
+        format_version: CURRENT_FORMAT_VERSION,
+        data: note,
+    };
+    let result = conn
+        .query_row(
+            "INSERT INTO notes (uuid, data, modified_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(uuid) DO UPDATE SET data = excluded.data, modified_at = excluded.modified_at
+             RETURNING id",
+            params![
+                uuid,
+                to_json(&envelope)?.to_string(),
+                note.content.modified_at as i64
+            ],
+            |row| row.get::<_, i64>(0),
+        )
+        .and_then(|id| {
+            conn.execute("DELETE FROM notes_fts WHERE rowid = ?1", params![id])?;
+            conn.execute(
+                "INSERT INTO notes_fts (rowid, title, body) VALUES (?1, ?2, ?3)",
+                params![id, note.content.title, note.content.body],
+            )
+        });
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => throw!("Error saving note {}: {}", uuid, e),
+    }
+}
+
+// Turn what the user typed into an FTS5 query. Every word is quoted so
+// punctuation can't be read as query syntax, and the last one matches as a
+// prefix like the in-memory index does
+fn fts_query(query: &str) -> Option<String> {
+    let words: Vec<String> = tokenize(query)
+        .into_iter()
+        .map(|(word, _, _)| format!("\"{}\"", word))
+        .collect();
+    match words.is_empty() {
+        true => None,
+        false => Some(format!("{}*", words.join(" "))),
+    }
+}
+
+fn snippet_html(snippet: &str) -> String {
+    escape_html(snippet)
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
+
+impl SqliteBackend {
+    // Every row, parsed from the note data `column` selects
+    fn select_all(&self, column: &str) -> Result<Vec<NoteFile>, String> {
+        let mut statement = self
+            .conn
+            .prepare(&format!("SELECT uuid, {} FROM notes", column))
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(|e| e.to_string())?;
+
+        let mut notes = vec![];
+        for row in rows {
+            let (uuid, data) = row.map_err(|e| e.to_string())?;
+            match self.parse(&data) {
+                Ok(note) => notes.push(note),
+                Err(e) => eprintln!("Error loading note {}: {}", uuid, e),
+            }
+        }
+        Ok(notes)
+    }
+}
+
+impl Backend for SqliteBackend {
+    fn list(&mut self) -> Result<Vec<NoteFile>, String> {
+        self.select_all("data")
+    }
+
+    // The body is blanked out in the query, so it never leaves the database
+    fn list_headers(&mut self) -> Result<Vec<NoteFile>, String> {
+        self.select_all("json_replace(data, '$.data.content.body', '')")
+    }
+
+    fn load(&self, uuid: &Uuid) -> Result<Option<NoteFile>, String> {
+        let data: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT data FROM notes WHERE uuid = ?1",
+                params![uuid.to_string()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        match data {
+            Some(data) => Ok(Some(self.parse(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&mut self, note: &NoteFile) -> Result<NoteFile, String> {
+        Ok(self.save_many(std::slice::from_ref(note))?.remove(0))
+    }
+
+    fn save_many(&mut self, notes: &[NoteFile]) -> Result<Vec<NoteFile>, String> {
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        for note in notes {
+            write_note(&tx, note)?;
+        }
+        if let Err(e) = tx.commit() {
+            throw!("Error saving notes: {}", e);
+        }
+        Ok(notes.to_vec())
+    }
+
+    fn delete(&mut self, uuid: &Uuid) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        let result = tx
+            .query_row(
+                "DELETE FROM notes WHERE uuid = ?1 RETURNING id",
+                params![uuid.to_string()],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+            .and_then(|id| match id {
+                Some(id) => tx.execute("DELETE FROM notes_fts WHERE rowid = ?1", params![id]),
+                None => Ok(0),
+            })
+            .and_then(|_| tx.commit());
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => throw!("Error deleting note {}: {}", uuid, e),
+        }
+    }
+
+    fn full_text(&self) -> bool {
+        true
+    }
+
+    // Rank with FTS5's bm25, weighting the title like the in-memory index does
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, String> {
+        let query = match fts_query(query) {
+            Some(query) => query,
+            None => return Ok(vec![]),
+        };
+        let sql = format!(
+            "SELECT notes.uuid, notes_fts.title, bm25(notes_fts, {}, 1.0) AS rank,
+                snippet(notes_fts, 1, '{}', '{}', '…', 24)
+             FROM notes_fts JOIN notes ON notes.id = notes_fts.rowid
+             WHERE notes_fts MATCH ?1
+             ORDER BY rank LIMIT ?2",
+            TITLE_WEIGHT, MATCH_START, MATCH_END
+        );
+        let mut statement = self.conn.prepare(&sql).map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(params![query, limit as i64], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, f64>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })
+            .map_err(|e| e.to_string())?;
+
+        let mut hits = vec![];
+        for row in rows {
+            let (uuid, title, rank, snippet) = row.map_err(|e| e.to_string())?;
+            let uuid = match Uuid::parse_str(&uuid) {
+                Ok(uuid) => uuid,
+                Err(_) => continue,
+            };
+            hits.push(SearchHit {
+                uuid,
+                title,
+                // bm25 is lower for better matches
+                score: -rank,
+                snippet: snippet_html(&snippet),
+            });
+        }
+        Ok(hits)
+    }
+
+    // A consistent copy of the database, even while it is being written to
+    fn backup_to(&self, dir: &PathBuf) -> Result<(), String> {
+        let target = dir.join(DATABASE_FILE);
+        ensure_parent_exists(&target)?;
+        let result = self
+            .conn
+            .execute("VACUUM INTO ?1", params![target.to_string_lossy()]);
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => throw!("Error backing up {}: {}", self.db_path.display(), e),
+        }
+    }
+
+    // Merge the FTS index so deleted rows' terms are gone too, rebuild the
+    // file without its free pages and empty the write-ahead log
+    fn compact(&mut self) -> Result<(), String> {
+        let result = self.conn.execute_batch(
+            "INSERT INTO notes_fts (notes_fts) VALUES ('optimize');
+             VACUUM;
+             PRAGMA wal_checkpoint(TRUNCATE);",
+        );
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => throw!("Error compacting {}: {}", self.db_path.display(), e),
+        }
+    }
+
+    // Swap the database for the backed up copy. The connection is closed
+    // meanwhile, and the write-ahead log of the old database dropped
+    fn restore_from(&mut self, dir: &PathBuf) -> Result<(), String> {
+        let source = dir.join(DATABASE_FILE);
+        if !source.is_file() {
+            return Ok(());
+        }
+        let placeholder = Connection::open_in_memory().map_err(|e| e.to_string())?;
+        if let Err((_, e)) = std::mem::replace(&mut self.conn, placeholder).close() {
+            throw!("Error closing {}: {}", self.db_path.display(), e);
+        }
+        for suffix in ["-wal", "-shm"] {
+            let path = PathBuf::from(format!("{}{}", self.db_path.display(), suffix));
+            if path.exists() {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    eprintln!("Error removing {}: {}", path.display(), e);
+                }
+            }
+        }
+        let copied = std::fs::copy(&source, &self.db_path);
+        self.conn = connect(&self.db_path)?;
+        if let Err(e) = copied {
+            throw!("Error restoring {}: {}", self.db_path.display(), e);
+        }
+        self.upgrade_schema()
+    }
+}
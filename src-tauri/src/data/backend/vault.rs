@@ -0,0 +1,163 @@
+use crate::core::utils::json::to_json;
+use crate::data::backend::Backend;
+use crate::data::migrations::MigrationReport;
+use crate::data::note::Note;
+use crate::data::vault::VaultKey;
+use crate::data::NoteFile;
+
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// Encrypts every note before handing it to the wrapped backend, leaving only
+// the uuid and location readable. While the vault is locked it holds no notes
+#[derive(Debug)]
+pub struct VaultBackend<B: Backend> {
+    inner: B,
+    key: VaultKey,
+}
+
+impl<B: Backend> VaultBackend<B> {
+    pub fn new(inner: B, key: VaultKey) -> Self {
+        Self { inner, key }
+    }
+}
+
+impl<B: Backend> Backend for VaultBackend<B> {
+    fn list(&mut self) -> Result<Vec<NoteFile>, String> {
+        if self.key.is_locked() {
+            return Ok(vec![]);
+        }
+        let mut notes = vec![];
+        for note in self.inner.list()? {
+            let file_path = note.file_path.to_owned();
+            match self.unseal(note) {
+                Ok(note) => notes.push(note),
+                Err(e) => eprintln!("Error decrypting {}: {}", file_path.display(), e),
+            }
+        }
+        Ok(notes)
+    }
+
+    // Headers are encrypted along with the body, so every note is read whole
+    fn list_headers(&mut self) -> Result<Vec<NoteFile>, String> {
+        let mut notes = self.list()?;
+        for note in notes.iter_mut() {
+            note.content.body = String::new();
+        }
+        Ok(notes)
+    }
+
+    fn load(&self, uuid: &Uuid) -> Result<Option<NoteFile>, String> {
+        if self.key.is_locked() {
+            return Ok(None);
+        }
+        match self.inner.load(uuid)? {
+            Some(note) => Ok(Some(self.unseal(note)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&mut self, note: &NoteFile) -> Result<NoteFile, String> {
+        let stored = self.inner.save(&self.seal(note)?)?;
+        let mut note = note.to_owned();
+        note.file_path = stored.file_path;
+        Ok(note)
+    }
+
+    fn save_many(&mut self, notes: &[NoteFile]) -> Result<Vec<NoteFile>, String> {
+        let sealed = notes
+            .iter()
+            .map(|note| self.seal(note))
+            .collect::<Result<Vec<NoteFile>, String>>()?;
+        let stored = self.inner.save_many(&sealed)?;
+        Ok(notes
+            .iter()
+            .zip(stored)
+            .map(|(note, stored)| {
+                let mut note = note.to_owned();
+                note.file_path = stored.file_path;
+                note
+            })
+            .collect())
+    }
+
+    fn delete(&mut self, uuid: &Uuid) -> Result<(), String> {
+        self.inner.delete(uuid)
+    }
+
+    fn migrate(&mut self, backup_root: &PathBuf) -> Result<MigrationReport, String> {
+        self.inner.migrate(backup_root)
+    }
+
+    // Backups hold the notes as stored, still encrypted
+    fn backup_to(&self, dir: &PathBuf) -> Result<(), String> {
+        self.inner.backup_to(dir)
+    }
+
+    fn restore_from(&mut self, dir: &PathBuf) -> Result<(), String> {
+        self.inner.restore_from(dir)
+    }
+
+    fn watch_dir(&self) -> Option<PathBuf> {
+        self.inner.watch_dir()
+    }
+
+    // Changes made while the vault is locked are picked up when it is unlocked
+    fn load_path(&mut self, path: &PathBuf) -> Result<Option<NoteFile>, String> {
+        if self.key.is_locked() {
+            return Ok(None);
+        }
+        match self.inner.load_path(path)? {
+            Some(note) => Ok(Some(self.unseal(note)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn forget_path(&mut self, path: &PathBuf) -> Option<Uuid> {
+        self.inner.forget_path(path)
+    }
+
+    fn seal(&self, note: &NoteFile) -> Result<NoteFile, String> {
+        let cipher = match self.key.cipher()? {
+            Some(cipher) => cipher,
+            None => return Ok(note.to_owned()),
+        };
+        let plaintext = to_json(&note.content)?.to_string();
+        Ok(NoteFile {
+            file_path: note.file_path.to_owned(),
+            uuid: note.uuid,
+            content: Note::default(),
+            vault: Some(cipher.seal(plaintext.as_bytes())?),
+            locked: note.locked.to_owned(),
+        })
+    }
+
+    fn unseal(&self, note: NoteFile) -> Result<NoteFile, String> {
+        let sealed = match &note.vault {
+            Some(sealed) => sealed,
+            // Written before the vault was created
+            None => return Ok(note),
+        };
+        let cipher = match self.key.cipher()? {
+            Some(cipher) => cipher,
+            None => throw!("The vault is locked"),
+        };
+        let content: Note = match serde_json::from_slice(&cipher.open(sealed)?) {
+            Ok(content) => content,
+            Err(e) => throw!("Could not parse decrypted note: {}", e),
+        };
+        Ok(NoteFile {
+            content,
+            vault: None,
+            ..note
+        })
+    }
+
+    fn encrypted(&self) -> bool {
+        self.key.is_enabled()
+    }
+
+    fn compact(&mut self) -> Result<(), String> {
+        self.inner.compact()
+    }
+}
@@ -0,0 +1,666 @@
+use crate::core::utils::fs::ensure_parent_exists;
+use crate::core::utils::json::to_json;
+use crate::core::utils::time::{from_file_date, now_millis, to_file_date};
+use crate::data::archive::{list_files, safe_path};
+use crate::data::backend::Backend;
+use crate::data::settings::Settings;
+use crate::data::Data;
+
+use atomicwrites::{AtomicFile, OverwriteBehavior};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+pub const BACKUPS_DIR: &str = "backups";
+const BACKUP_MANIFEST: &str = "backup.json";
+// Where files go in a backup: the data directory, and what the backend keeps elsewhere
+const DATA_PREFIX: &str = "data";
+const BACKEND_PREFIX: &str = "backend";
+// How often the worker checks whether a backup is due
+const WORKER_TICK: Duration = Duration::from_secs(60);
+const HOUR_MILLIS: u64 = 60 * 60 * 1000;
+const DAY_MILLIS: u64 = 24 * HOUR_MILLIS;
+
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackupReason {
+    Scheduled,
+    // Taken when the app exits
+    Exit,
+    Manual,
+    // Taken before restoring another backup, so the restore can be undone
+    PreRestore,
+}
+
+impl BackupReason {
+    fn name(&self) -> &'static str {
+        match self {
+            BackupReason::Scheduled => "scheduled",
+            BackupReason::Exit => "exit",
+            BackupReason::Manual => "manual",
+            BackupReason::PreRestore => "pre-restore",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "scheduled" => Some(BackupReason::Scheduled),
+            "exit" => Some(BackupReason::Exit),
+            "manual" => Some(BackupReason::Manual),
+            "pre-restore" => Some(BackupReason::PreRestore),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+struct BackupFile {
+    path: String,
+    size: u64,
+}
+
+// `backup.json`, written last so a backup without one is known to be incomplete
+#[derive(Serialize, Debug, Deserialize, Clone)]
+struct BackupManifest {
+    created_at: u64,
+    reason: BackupReason,
+    // Whether the notes were sealed by the vault. Backups that don't say are taken as plaintext
+    #[serde(default)]
+    encrypted: bool,
+    files: Vec<BackupFile>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct BackupInfo {
+    // The file name, e.g. `backup-20221201T103000.000Z-scheduled.zip`
+    pub id: String,
+    pub created_at: u64,
+    pub reason: BackupReason,
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct RestoreReport {
+    pub restored: BackupInfo,
+    // The backup of what was there before
+    pub previous: BackupInfo,
+}
+
+fn backup_name(created_at: u64, reason: BackupReason) -> String {
+    format!("backup-{}-{}.zip", to_file_date(created_at), reason.name())
+}
+
+fn parse_name(name: &str) -> Option<(u64, BackupReason)> {
+    let stem = name.strip_prefix("backup-")?.strip_suffix(".zip")?;
+    let (date, reason) = stem.split_once('-')?;
+    Some((from_file_date(date)?, BackupReason::from_name(reason)?))
+}
+
+// Files copied out of the data directory and the backend, to be zipped
+// into a backup without holding up the notes meanwhile
+#[derive(Debug)]
+pub struct Snapshot {
+    staging: PathBuf,
+    created_at: u64,
+    reason: BackupReason,
+    encrypted: bool,
+}
+
+fn remove_dir(dir: &PathBuf) {
+    if dir.exists() {
+        if let Err(e) = std::fs::remove_dir_all(dir) {
+            eprintln!("Error removing {}: {}", dir.display(), e);
+        }
+    }
+}
+
+// Move a data directory that was set aside back, in place of whatever is there now
+fn put_back(replaced: &PathBuf, data_path: &PathBuf) {
+    remove_dir(data_path);
+    if replaced.exists() {
+        if let Err(e) = std::fs::rename(replaced, data_path) {
+            eprintln!("Error moving {} back: {}", replaced.display(), e);
+        }
+    }
+}
+
+// Snapshots of the data directory, zipped into `app_dir/backups`
+#[derive(Debug, Clone)]
+pub struct Backups {
+    pub backups_path: PathBuf,
+    interval_hours: u64,
+    keep_daily: usize,
+    keep_weekly: usize,
+}
+
+impl Backups {
+    pub fn new(app_dir: &PathBuf, settings: &Settings) -> Self {
+        Self {
+            backups_path: app_dir.join(BACKUPS_DIR),
+            interval_hours: settings.backup_interval_hours,
+            keep_daily: settings.backup_keep_daily,
+            keep_weekly: settings.backup_keep_weekly,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.interval_hours > 0
+    }
+
+    // Every backup, newest first
+    pub fn list(&self) -> Vec<BackupInfo> {
+        let entries = match std::fs::read_dir(&self.backups_path) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut backups = vec![];
+        for entry in entries.flatten() {
+            let id = entry.file_name().to_string_lossy().to_string();
+            if let Some((created_at, reason)) = parse_name(&id) {
+                backups.push(BackupInfo {
+                    id,
+                    created_at,
+                    reason,
+                    size: entry.metadata().map(|m| m.len()).unwrap_or(0),
+                });
+            }
+        }
+        backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+        backups
+    }
+
+    pub fn is_due(&self) -> bool {
+        if !self.enabled() {
+            return false;
+        }
+        match self.list().first() {
+            Some(latest) => {
+                now_millis().saturating_sub(latest.created_at) >= self.interval_hours * HOUR_MILLIS
+            }
+            None => true,
+        }
+    }
+
+    fn get(&self, id: &str) -> Result<BackupInfo, String> {
+        match self.list().into_iter().find(|b| b.id == id) {
+            Some(backup) => Ok(backup),
+            None => throw!("Backup {} does not exist", id),
+        }
+    }
+
+    // Zip the data directory and the backend's own files, then read the
+    // backup back to make sure it is complete
+    pub fn create<B: Backend>(
+        &self,
+        data_path: &PathBuf,
+        backend: &B,
+        reason: BackupReason,
+    ) -> Result<BackupInfo, String> {
+        self.write_snapshot(self.snapshot(data_path, backend, reason)?)
+    }
+
+    // Copy the files a backup holds. This is the only part of a backup that
+    // needs the notes to stay put
+    pub fn snapshot<B: Backend>(
+        &self,
+        data_path: &PathBuf,
+        backend: &B,
+        reason: BackupReason,
+    ) -> Result<Snapshot, String> {
+        let created_at = now_millis();
+        let staging = self.backups_path.join(format!(".staging-{}", created_at));
+        remove_dir(&staging);
+        let copied = copy_dir(data_path, &staging.join(DATA_PREFIX))
+            .and_then(|_| backend.backup_to(&staging.join(BACKEND_PREFIX)));
+        if let Err(e) = copied {
+            remove_dir(&staging);
+            throw!("Could not copy notes for a backup: {}", e);
+        }
+        Ok(Snapshot {
+            staging,
+            created_at,
+            reason,
+            encrypted: backend.encrypted(),
+        })
+    }
+
+    // Zip up and verify a snapshot. A backup that fails to verify is deleted
+    pub fn write_snapshot(&self, snapshot: Snapshot) -> Result<BackupInfo, String> {
+        let id = backup_name(snapshot.created_at, snapshot.reason);
+        let path = self.backups_path.join(&id);
+        let written = self.write(&path, &snapshot);
+        remove_dir(&snapshot.staging);
+        written?;
+
+        if let Err(e) = self.verify(&path) {
+            if let Err(e) = std::fs::remove_file(&path) {
+                eprintln!("Error removing {}: {}", path.display(), e);
+            }
+            throw!("Backup {} failed to verify: {}", id, e);
+        }
+        self.get(&id)
+    }
+
+    fn write(&self, path: &PathBuf, snapshot: &Snapshot) -> Result<(), String> {
+        let mut files = vec![];
+        if snapshot.staging.is_dir() {
+            list_files(&snapshot.staging, &PathBuf::new(), &mut files)?;
+        }
+        let sources: Vec<(String, PathBuf)> = files
+            .into_iter()
+            .map(|relative| {
+                let parts: Vec<String> = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect();
+                (parts.join("/"), snapshot.staging.join(relative))
+            })
+            .collect();
+
+        ensure_parent_exists(path)?;
+        let file = AtomicFile::new(path, OverwriteBehavior::DisallowOverwrite);
+        let written = file.write(|file| {
+            let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+            let mut zip = ZipWriter::new(file);
+            let mut manifest = BackupManifest {
+                created_at: snapshot.created_at,
+                reason: snapshot.reason,
+                encrypted: snapshot.encrypted,
+                files: vec![],
+            };
+            for (name, source) in sources.iter() {
+                let mut input = match std::fs::File::open(source) {
+                    Ok(input) => input,
+                    Err(e) => throw!("Error reading {}: {}", source.display(), e),
+                };
+                if let Err(e) = zip.start_file(name, options) {
+                    throw!("Error adding {}: {}", name, e);
+                }
+                match std::io::copy(&mut input, &mut zip) {
+                    Ok(size) => manifest.files.push(BackupFile {
+                        path: name.to_owned(),
+                        size,
+                    }),
+                    Err(e) => throw!("Error adding {}: {}", name, e),
+                }
+            }
+            let manifest = to_json(&manifest)?.to_string();
+            let result = zip
+                .start_file(BACKUP_MANIFEST, options)
+                .map_err(|e| e.to_string())
+                .and_then(|_| {
+                    std::io::Write::write_all(&mut zip, manifest.as_bytes())
+                        .map_err(|e| e.to_string())
+                })
+                .and_then(|_| zip.finish().map(|_| ()).map_err(|e| e.to_string()));
+            match result {
+                Ok(_) => Ok(()),
+                Err(e) => throw!("Error writing backup: {}", e),
+            }
+        });
+        match written {
+            Ok(_) => Ok(()),
+            Err(e) => throw!("Could not write {}: {}", path.display(), e),
+        }
+    }
+
+    // Read every file in a backup, which checks it against the checksums in
+    // the zip, and compare them with the manifest
+    fn verify(&self, path: &PathBuf) -> Result<BackupManifest, String> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => throw!("Could not open {}: {}", path.display(), e),
+        };
+        let mut zip = match ZipArchive::new(file) {
+            Ok(zip) => zip,
+            Err(e) => throw!("Not a zip archive: {}", e),
+        };
+        let manifest: BackupManifest = {
+            let mut bytes = vec![];
+            let read = match zip.by_name(BACKUP_MANIFEST) {
+                Ok(mut entry) => entry.read_to_end(&mut bytes).map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = read {
+                throw!("Could not read {}: {}", BACKUP_MANIFEST, e);
+            }
+            match serde_json::from_slice(&bytes) {
+                Ok(manifest) => manifest,
+                Err(e) => throw!("Could not parse {}: {}", BACKUP_MANIFEST, e),
+            }
+        };
+        for expected in manifest.files.iter() {
+            let mut entry = match zip.by_name(&expected.path) {
+                Ok(entry) => entry,
+                Err(e) => throw!("{} is missing: {}", expected.path, e),
+            };
+            match std::io::copy(&mut entry, &mut std::io::sink()) {
+                Ok(size) if size == expected.size => {}
+                Ok(size) => throw!(
+                    "{} has {} bytes instead of {}",
+                    expected.path,
+                    size,
+                    expected.size
+                ),
+                Err(e) => throw!("{} is damaged: {}", expected.path, e),
+            }
+        }
+        Ok(manifest)
+    }
+
+    // Keep the latest backup of each of the last `keep_daily` days and
+    // `keep_weekly` weeks that have one, plus the newest backup, and delete
+    // the rest. Manual backups and those taken before a restore are only
+    // deleted by hand. Returns the ids of the deleted backups
+    pub fn prune(&self) -> Result<Vec<String>, String> {
+        let backups = self.list();
+        let mut days = HashSet::new();
+        let mut weeks = HashSet::new();
+        let mut removed = vec![];
+        for (i, backup) in backups.iter().enumerate() {
+            // A pre-restore backup is the only way to undo a restore
+            if matches!(
+                backup.reason,
+                BackupReason::Manual | BackupReason::PreRestore
+            ) {
+                continue;
+            }
+            let day = backup.created_at / DAY_MILLIS;
+            // The epoch was a Thursday; weeks start on Monday
+            let week = (day + 3) / 7;
+            let mut keep = i == 0;
+            if !days.contains(&day) && days.len() < self.keep_daily {
+                days.insert(day);
+                keep = true;
+            }
+            if !weeks.contains(&week) && weeks.len() < self.keep_weekly {
+                weeks.insert(week);
+                keep = true;
+            }
+            if !keep {
+                let path = self.backups_path.join(&backup.id);
+                if let Err(e) = std::fs::remove_file(&path) {
+                    throw!("Error removing {}: {}", path.display(), e);
+                }
+                removed.push(backup.id.to_owned());
+            }
+        }
+        Ok(removed)
+    }
+
+    // Delete every backup, e.g. the plaintext ones once the vault is
+    // created. Returns the ids of the deleted backups
+    pub fn remove_all(&self) -> Result<Vec<String>, String> {
+        let mut removed = vec![];
+        for backup in self.list() {
+            let path = self.backups_path.join(&backup.id);
+            if let Err(e) = std::fs::remove_file(&path) {
+                throw!("Error removing {}: {}", path.display(), e);
+            }
+            removed.push(backup.id);
+        }
+        Ok(removed)
+    }
+
+    // Replace the data directory, and the backend's own files, with a
+    // backup. What was there is backed up first
+    pub fn restore<B: Backend>(
+        &self,
+        id: &str,
+        data_path: &PathBuf,
+        backend: &mut B,
+    ) -> Result<RestoreReport, String> {
+        let restored = self.get(id)?;
+        let path = self.backups_path.join(id);
+        let manifest = self.verify(&path)?;
+        // Restoring it would put notes back on disk unencrypted
+        if backend.encrypted() && !manifest.encrypted {
+            throw!(
+                "Backup {} was made before the vault and holds notes unencrypted",
+                id
+            );
+        }
+        let previous = self.create(data_path, &*backend, BackupReason::PreRestore)?;
+
+        let staging = self.backups_path.join(format!(".restore-{}", now_millis()));
+        remove_dir(&staging);
+        let extracted = self.extract(&path, &manifest, &staging);
+        if let Err(e) = extracted {
+            remove_dir(&staging);
+            throw!("Could not restore {}: {}", id, e);
+        }
+
+        // Moved aside rather than deleted until the restored copy is in place
+        let replaced = self
+            .backups_path
+            .join(format!(".replaced-{}", now_millis()));
+        if data_path.exists() {
+            if let Err(e) = std::fs::rename(data_path, &replaced) {
+                remove_dir(&staging);
+                throw!("Could not move {} aside: {}", data_path.display(), e);
+            }
+        }
+        let restored_data = staging.join(DATA_PREFIX);
+        let moved = match restored_data.exists() {
+            true => std::fs::rename(&restored_data, data_path),
+            false => std::fs::create_dir_all(data_path),
+        };
+        if let Err(e) = moved {
+            put_back(&replaced, data_path);
+            remove_dir(&staging);
+            throw!("Could not restore {}: {}", data_path.display(), e);
+        }
+
+        // The notes have to match the backend's files, so a backend that
+        // fails to restore gets the old data directory back
+        let result = backend.restore_from(&staging.join(BACKEND_PREFIX));
+        remove_dir(&staging);
+        if let Err(e) = result {
+            put_back(&replaced, data_path);
+            throw!("Could not restore {}: {}", id, e);
+        }
+        remove_dir(&replaced);
+        Ok(RestoreReport { restored, previous })
+    }
+
+    fn extract(
+        &self,
+        path: &PathBuf,
+        manifest: &BackupManifest,
+        staging: &PathBuf,
+    ) -> Result<(), String> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => throw!("Could not open {}: {}", path.display(), e),
+        };
+        let mut zip = match ZipArchive::new(file) {
+            Ok(zip) => zip,
+            Err(e) => throw!("Not a zip archive: {}", e),
+        };
+        for expected in manifest.files.iter() {
+            let target = match safe_path(&expected.path) {
+                Some(relative) => staging.join(relative),
+                None => throw!("{} is not a path that can be restored", expected.path),
+            };
+            let mut entry = match zip.by_name(&expected.path) {
+                Ok(entry) => entry,
+                Err(e) => throw!("{} is missing: {}", expected.path, e),
+            };
+            ensure_parent_exists(&target)?;
+            let mut output = match std::fs::File::create(&target) {
+                Ok(output) => output,
+                Err(e) => throw!("Error writing {}: {}", target.display(), e),
+            };
+            if let Err(e) = std::io::copy(&mut entry, &mut output) {
+                throw!("Error writing {}: {}", target.display(), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Copy a directory's files into `target`, which is created
+fn copy_dir(dir: &PathBuf, target: &PathBuf) -> Result<(), String> {
+    let mut files = vec![];
+    if dir.is_dir() {
+        list_files(dir, &PathBuf::new(), &mut files)?;
+    }
+    for relative in files {
+        let copy = target.join(&relative);
+        ensure_parent_exists(&copy)?;
+        if let Err(e) = std::fs::copy(dir.join(&relative), &copy) {
+            throw!("Error copying {}: {}", relative.display(), e);
+        }
+    }
+    Ok(())
+}
+
+// Back up, holding the notes only while their files are copied. The
+// backup is zipped and verified after they're released
+fn back_up(data: &Data, reason: BackupReason) -> Result<BackupInfo, String> {
+    let (backups, snapshot) = {
+        let cache = data.0.lock().unwrap();
+        (cache.backups().clone(), cache.backup_snapshot(reason)?)
+    };
+    let backup = backups.write_snapshot(snapshot)?;
+    backups.prune()?;
+    Ok(backup)
+}
+
+// Back up on the schedule in the settings for as long as the app runs
+pub fn start_worker(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        let data = app.state::<Data>();
+        let due = data.0.lock().unwrap().backup_due();
+        if due {
+            if let Err(e) = back_up(&data, BackupReason::Scheduled) {
+                eprintln!("Error backing up notes: {}", e);
+            }
+        }
+        std::thread::sleep(WORKER_TICK);
+    });
+}
+
+#[tauri::command]
+pub fn list_backups(data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.list_backups())
+}
+
+#[tauri::command]
+pub fn create_backup(data: State<'_, Data>) -> Result<Value, String> {
+    to_json(&back_up(&data, BackupReason::Manual)?)
+}
+
+#[tauri::command]
+pub fn restore_backup(id: String, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.restore_backup(&id)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::NoteFile;
+    use uuid::Uuid;
+
+    fn backups(name: &str) -> (PathBuf, Backups) {
+        let app_dir = std::env::temp_dir().join(format!("notes-{}-{}", name, Uuid::new_v4()));
+        let settings = Settings {
+            backup_keep_daily: 1,
+            backup_keep_weekly: 0,
+            ..Default::default()
+        };
+        let backups = Backups::new(&app_dir, &settings);
+        (app_dir, backups)
+    }
+
+    // Keeps no notes and can't restore its own files
+    #[derive(Debug)]
+    struct FailingRestore;
+
+    impl Backend for FailingRestore {
+        fn list(&mut self) -> Result<Vec<NoteFile>, String> {
+            Ok(vec![])
+        }
+        fn load(&self, _uuid: &Uuid) -> Result<Option<NoteFile>, String> {
+            Ok(None)
+        }
+        fn save(&mut self, note: &NoteFile) -> Result<NoteFile, String> {
+            Ok(note.to_owned())
+        }
+        fn delete(&mut self, _uuid: &Uuid) -> Result<(), String> {
+            Ok(())
+        }
+        fn restore_from(&mut self, _dir: &PathBuf) -> Result<(), String> {
+            throw!("Disk full")
+        }
+    }
+
+    #[test]
+    fn prune_keeps_manual_and_pre_restore_backups() {
+        let (app_dir, backups) = backups("prune");
+        std::fs::create_dir_all(&backups.backups_path).unwrap();
+        let now = now_millis();
+        let reasons = [
+            BackupReason::Scheduled,
+            BackupReason::PreRestore,
+            BackupReason::Exit,
+            BackupReason::Manual,
+            BackupReason::Scheduled,
+        ];
+        // Newest first, a day apart
+        let ids: Vec<String> = reasons
+            .iter()
+            .enumerate()
+            .map(|(i, reason)| backup_name(now - i as u64 * DAY_MILLIS, *reason))
+            .collect();
+        for id in ids.iter() {
+            std::fs::write(backups.backups_path.join(id), "").unwrap();
+        }
+
+        let removed = backups.prune().unwrap();
+        assert_eq!(removed, vec![ids[2].to_owned(), ids[4].to_owned()]);
+        let kept: Vec<String> = backups.list().into_iter().map(|b| b.id).collect();
+        assert_eq!(
+            kept,
+            vec![ids[0].to_owned(), ids[1].to_owned(), ids[3].to_owned()]
+        );
+        std::fs::remove_dir_all(&app_dir).unwrap();
+    }
+
+    #[test]
+    fn failed_backend_restore_puts_the_data_directory_back() {
+        let (app_dir, backups) = backups("restore");
+        let data_path = app_dir.join("data");
+        std::fs::create_dir_all(&data_path).unwrap();
+        std::fs::write(data_path.join("note.json"), "backed up").unwrap();
+        let backup = backups
+            .create(&data_path, &FailingRestore, BackupReason::Manual)
+            .unwrap();
+        std::fs::write(data_path.join("note.json"), "current").unwrap();
+
+        assert!(backups
+            .restore(&backup.id, &data_path, &mut FailingRestore)
+            .is_err());
+        assert_eq!(
+            std::fs::read_to_string(data_path.join("note.json")).unwrap(),
+            "current"
+        );
+        let leftovers = std::fs::read_dir(&backups.backups_path)
+            .unwrap()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with('.'))
+            .count();
+        assert_eq!(leftovers, 0);
+        std::fs::remove_dir_all(&app_dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+use uuid::Uuid;
+
+// How many bytes of note bodies to keep in memory
+pub const BODY_CACHE_SIZE: usize = 16 * 1024 * 1024;
+
+// Note bodies most recently read or written, up to `capacity` bytes. The
+// least recently used are dropped first and read from the backend again when
+// next needed
+#[derive(Debug, Clone, Default)]
+pub struct BodyCache {
+    capacity: usize,
+    size: usize,
+    clock: u64,
+    bodies: HashMap<Uuid, (u64, String)>,
+    // Uuids by when they were last used
+    recency: BTreeMap<u64, Uuid>,
+    // A hash of every note's body, kept after it is dropped, to tell whether
+    // a body read back from disk is the one already known
+    digests: HashMap<Uuid, u64>,
+}
+
+// Stable across runs, since digests are persisted with the summary index
+pub fn digest(body: &str) -> u64 {
+    let hash = Md5::digest(body.as_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+// The size and modification time of a note's file when its body was read.
+// Persisted indexes compare it rather than the note's own `modified_at`, which
+// stays the same when the file is edited in another app
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub len: u64,
+    pub modified: u64,
+}
+
+impl FileStamp {
+    // `None` for notes without a file of their own, like rows in SQLite
+    pub fn of(path: &PathBuf) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            len: metadata.len(),
+            modified: modified.as_nanos() as u64,
+        })
+    }
+}
+
+impl BodyCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&mut self, uuid: &Uuid) -> Option<String> {
+        self.clock += 1;
+        let (used, body) = self.bodies.get_mut(uuid)?;
+        self.recency.remove(used);
+        self.recency.insert(self.clock, *uuid);
+        *used = self.clock;
+        Some(body.to_owned())
+    }
+
+    pub fn insert(&mut self, uuid: &Uuid, body: &str) {
+        self.remove(uuid);
+        self.digests.insert(*uuid, digest(body));
+        // Bodies bigger than the whole cache are always read from the backend
+        if body.len() > self.capacity {
+            return;
+        }
+        self.clock += 1;
+        self.size += body.len();
+        self.bodies.insert(*uuid, (self.clock, body.to_string()));
+        self.recency.insert(self.clock, *uuid);
+        self.evict();
+    }
+
+    pub fn remove(&mut self, uuid: &Uuid) {
+        self.digests.remove(uuid);
+        if let Some((used, body)) = self.bodies.remove(uuid) {
+            self.recency.remove(&used);
+            self.size -= body.len();
+        }
+    }
+
+    // Know a body by its digest alone, e.g. one persisted from an earlier run
+    pub fn remember(&mut self, uuid: &Uuid, digest: u64) {
+        self.digests.insert(*uuid, digest);
+    }
+
+    pub fn digest(&self, uuid: &Uuid) -> Option<u64> {
+        self.digests.get(uuid).copied()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new(self.capacity);
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.capacity {
+            let oldest = match self.recency.keys().next() {
+                Some(used) => *used,
+                None => return,
+            };
+            if let Some(uuid) = self.recency.remove(&oldest) {
+                if let Some((_, body)) = self.bodies.remove(&uuid) {
+                    self.size -= body.len();
+                }
+            }
+        }
+    }
+}
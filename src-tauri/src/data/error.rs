@@ -0,0 +1,45 @@
+use serde::Serialize;
+use uuid::Uuid;
+
+// Errors the frontend can tell apart, sent as `{ "kind": "not-found", ... }`.
+// Commands that only report a message keep using `String`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum NoteError {
+    // Every requested uuid that has no note
+    NotFound { uuids: Vec<Uuid> },
+    Other { message: String },
+}
+
+impl NoteError {
+    pub fn not_found(uuid: &Uuid) -> Self {
+        NoteError::NotFound { uuids: vec![*uuid] }
+    }
+}
+
+impl std::fmt::Display for NoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoteError::NotFound { uuids } => {
+                let uuids: Vec<String> = uuids.iter().map(|uuid| uuid.to_string()).collect();
+                match uuids.len() {
+                    1 => write!(f, "Note {} does not exist", uuids[0]),
+                    _ => write!(f, "Notes {} do not exist", uuids.join(", ")),
+                }
+            }
+            NoteError::Other { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for NoteError {
+    fn from(message: String) -> Self {
+        NoteError::Other { message }
+    }
+}
+
+impl From<NoteError> for String {
+    fn from(error: NoteError) -> Self {
+        error.to_string()
+    }
+}
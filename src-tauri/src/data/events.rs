@@ -0,0 +1,69 @@
+use crate::data::{Data, NoteFile};
+
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager};
+use uuid::Uuid;
+
+// A change to a single note, sent to the windows as the event named by `name`
+// so they can patch their list instead of fetching every note again
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum NoteEvent {
+    Created(NoteFile),
+    Updated(NoteFile),
+    Deleted { uuid: Uuid },
+}
+
+impl NoteEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteEvent::Created(_) => "note-created",
+            NoteEvent::Updated(_) => "note-updated",
+            NoteEvent::Deleted { .. } => "note-deleted",
+        }
+    }
+}
+
+// Listeners run while the notes are locked, so must not call back into the store
+type Listener = Box<dyn Fn(&NoteEvent) + Send>;
+
+// Shared between the `Store` and its `Notes`, which emit an event for every
+// note they save or delete
+#[derive(Clone, Default)]
+pub struct EventBus {
+    listeners: Arc<Mutex<Vec<Listener>>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self, listener: Listener) {
+        let mut listeners = self.listeners.lock().unwrap();
+        listeners.push(listener);
+    }
+
+    pub fn emit(&self, event: NoteEvent) {
+        let listeners = self.listeners.lock().unwrap();
+        for listener in listeners.iter() {
+            listener(&event);
+        }
+    }
+}
+
+impl std::fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let listeners = self.listeners.lock().unwrap();
+        write!(f, "EventBus({} listeners)", listeners.len())
+    }
+}
+
+// Forward every note event to the open windows
+pub fn emit_to_windows(app: AppHandle) {
+    let data = app.state::<Data>();
+    let cache = data.0.lock().unwrap();
+    let handle = app.clone();
+    cache.subscribe(Box::new(move |event| {
+        if let Err(e) = handle.emit_all(event.name(), event) {
+            eprintln!("Error sending {}: {}", event.name(), e);
+        }
+    }));
+}
@@ -0,0 +1,223 @@
+use crate::core::utils::json::to_json;
+use crate::data::links::LinkIndex;
+use crate::data::notebooks::Notebooks;
+use crate::data::search::escape_html;
+use crate::data::{Data, NoteFile, NoteFilter};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use tauri::State;
+use uuid::Uuid;
+
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    // Graphviz
+    Dot,
+    // Gephi, yEd, Cytoscape
+    Graphml,
+    // `{ nodes, edges }`, e.g. for d3
+    Json,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Note,
+    Tag,
+}
+
+impl NodeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            NodeKind::Note => "note",
+            NodeKind::Tag => "tag",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct GraphNode {
+    // `note:<uuid>` or `tag:<name>`
+    pub id: String,
+    pub kind: NodeKind,
+    pub label: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    // A note linking to another note
+    Link,
+    // A note carrying a tag
+    Tag,
+}
+
+impl EdgeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Link => "link",
+            EdgeKind::Tag => "tag",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub kind: EdgeKind,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+pub struct Graph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+fn note_id(uuid: &Uuid) -> String {
+    format!("note:{}", uuid)
+}
+
+fn tag_id(tag: &str) -> String {
+    format!("tag:{}", tag)
+}
+
+// Build the graph of notes, the links between them and their tags
+pub fn build_graph(
+    entries: &HashMap<Uuid, NoteFile>,
+    links: &LinkIndex,
+    notebooks: &Notebooks,
+    filter: &NoteFilter,
+) -> Graph {
+    let mut included: Vec<(&Uuid, &NoteFile)> = entries
+        .iter()
+        .filter(|(_, note)| filter.matches(&note.content, notebooks))
+        .collect();
+    // Sorted so that exports of the same notes are identical
+    included.sort_by_key(|(uuid, _)| **uuid);
+    let uuids: HashSet<Uuid> = included.iter().map(|(uuid, _)| **uuid).collect();
+
+    let mut graph = Graph::default();
+    let mut tags = BTreeSet::new();
+    for (uuid, note) in included.iter() {
+        graph.nodes.push(GraphNode {
+            id: note_id(uuid),
+            kind: NodeKind::Note,
+            label: note.content.title.to_owned(),
+        });
+        for (_, target) in links.outgoing(uuid) {
+            match target {
+                Some(target) if target != **uuid && uuids.contains(&target) => {
+                    graph.edges.push(GraphEdge {
+                        source: note_id(uuid),
+                        target: note_id(&target),
+                        kind: EdgeKind::Link,
+                    })
+                }
+                _ => {}
+            }
+        }
+        for tag in note.content.tags.iter() {
+            tags.insert(tag.to_owned());
+            graph.edges.push(GraphEdge {
+                source: note_id(uuid),
+                target: tag_id(tag),
+                kind: EdgeKind::Tag,
+            });
+        }
+    }
+    for tag in tags {
+        graph.nodes.push(GraphNode {
+            id: tag_id(&tag),
+            kind: NodeKind::Tag,
+            label: format!("#{}", tag),
+        });
+    }
+    graph
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub fn to_dot(graph: &Graph) -> String {
+    let mut dot = String::from("digraph notes {\n");
+    for node in graph.nodes.iter() {
+        let shape = match node.kind {
+            NodeKind::Note => "box",
+            NodeKind::Tag => "ellipse",
+        };
+        dot.push_str(&format!(
+            "  \"{}\" [label=\"{}\", shape={}];\n",
+            escape_dot(&node.id),
+            escape_dot(&node.label),
+            shape
+        ));
+    }
+    for edge in graph.edges.iter() {
+        let style = match edge.kind {
+            EdgeKind::Link => "solid",
+            EdgeKind::Tag => "dashed",
+        };
+        dot.push_str(&format!(
+            "  \"{}\" -> \"{}\" [style={}];\n",
+            escape_dot(&edge.source),
+            escape_dot(&edge.target),
+            style
+        ));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+pub fn to_graphml(graph: &Graph) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+        "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+        "  <key id=\"kind\" for=\"all\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+        "  <graph id=\"notes\" edgedefault=\"directed\">\n",
+    ));
+    for node in graph.nodes.iter() {
+        xml.push_str(&format!(
+            "    <node id=\"{}\"><data key=\"label\">{}</data><data key=\"kind\">{}</data></node>\n",
+            escape_html(&node.id),
+            escape_html(&node.label),
+            node.kind.name()
+        ));
+    }
+    for (i, edge) in graph.edges.iter().enumerate() {
+        xml.push_str(&format!(
+            "    <edge id=\"e{}\" source=\"{}\" target=\"{}\"><data key=\"kind\">{}</data></edge>\n",
+            i,
+            escape_html(&edge.source),
+            escape_html(&edge.target),
+            edge.kind.name()
+        ));
+    }
+    xml.push_str("  </graph>\n</graphml>\n");
+    xml
+}
+
+// Export the graph of notes. JSON comes back as `{ nodes, edges }`, the other
+// formats as a string for the frontend to save
+#[tauri::command]
+pub fn export_graph(
+    format: GraphFormat,
+    filter: Option<String>,
+    notebook: Option<Uuid>,
+    data: State<'_, Data>,
+) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    let graph = cache.get_graph(&NoteFilter::new(filter.as_deref(), notebook)?)?;
+
+    match format {
+        GraphFormat::Dot => to_json(&to_dot(&graph)),
+        GraphFormat::Graphml => to_json(&to_graphml(&graph)),
+        GraphFormat::Json => to_json(&graph),
+    }
+}
@@ -0,0 +1,817 @@
+use crate::core::utils::json::to_json;
+use crate::core::utils::time::now_millis;
+use crate::data::backend::Backend;
+use crate::data::import::{
+    store_attachment, ImportProgress, ImportReport, ImportedNote, VAULT_ATTACHMENT,
+};
+use crate::data::note::Note;
+use crate::data::notebooks::Notebooks;
+use crate::data::{Data, Notes};
+
+use chrono::DateTime;
+use md5::{Digest, Md5};
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::Reader;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::path::PathBuf;
+use tauri::{State, Window};
+
+// A file attached to a note, stored under the MD5 of its bytes, which is how
+// `<en-media hash="...">` refers to it
+#[derive(Debug, Clone)]
+struct Media {
+    url: String,
+    name: String,
+    mime: String,
+}
+
+#[derive(Debug, Default)]
+struct Resource {
+    data: String,
+    mime: String,
+    file_name: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct EnexNote {
+    title: String,
+    content: String,
+    created: Option<u64>,
+    updated: Option<u64>,
+    tags: Vec<String>,
+    media: HashMap<String, Media>,
+}
+
+// Dates look like `20221201T103000Z`, always in UTC
+fn parse_date(date: &str) -> Option<u64> {
+    let date = format!("{} +0000", date.trim());
+    match DateTime::parse_from_str(&date, "%Y%m%dT%H%M%SZ %z") {
+        Ok(date) => u64::try_from(date.timestamp_millis()).ok(),
+        Err(_) => None,
+    }
+}
+
+fn extension_for(mime: &str) -> &str {
+    match mime {
+        "image/jpeg" => "jpg",
+        "image/svg+xml" => "svg",
+        "audio/mpeg" => "mp3",
+        "text/plain" => "txt",
+        mime => mime.rsplit('/').next().unwrap_or("bin"),
+    }
+}
+
+// Decode a resource and copy it into the data directory
+fn store_resource(data_path: &PathBuf, resource: &Resource) -> Result<(String, Media), String> {
+    let encoded: String = resource
+        .data
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let bytes = match base64::decode(encoded) {
+        Ok(bytes) => bytes,
+        Err(e) => throw!("Could not decode attachment: {}", e),
+    };
+    let hash = format!("{:x}", Md5::digest(&bytes));
+    let name = match &resource.file_name {
+        Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+        _ => format!("attachment.{}", extension_for(&resource.mime)),
+    };
+    let url = store_attachment(data_path, &name, &bytes)?;
+    Ok((
+        hash,
+        Media {
+            url,
+            name,
+            mime: resource.mime.to_owned(),
+        },
+    ))
+}
+
+// Named entities ENML allows on top of XML's own
+fn html_entity(name: &str) -> Option<&'static str> {
+    match name {
+        "nbsp" => Some("\u{a0}"),
+        "ndash" => Some("–"),
+        "mdash" => Some("—"),
+        "hellip" => Some("…"),
+        "lsquo" => Some("‘"),
+        "rsquo" => Some("’"),
+        "ldquo" => Some("“"),
+        "rdquo" => Some("”"),
+        "laquo" => Some("«"),
+        "raquo" => Some("»"),
+        "bull" => Some("•"),
+        "middot" => Some("·"),
+        "copy" => Some("©"),
+        "reg" => Some("®"),
+        "trade" => Some("™"),
+        "euro" => Some("€"),
+        "times" => Some("×"),
+        _ => None,
+    }
+}
+
+// Text with an entity that isn't known is kept as written
+fn unescape(text: &BytesText) -> String {
+    match text.unescape_with(html_entity) {
+        Ok(text) => text.to_string(),
+        Err(_) => String::from_utf8_lossy(text).to_string(),
+    }
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    match element.try_get_attribute(name) {
+        Ok(Some(value)) => value
+            .unescape_value_with(html_entity)
+            .ok()
+            .map(|v| v.to_string()),
+        _ => None,
+    }
+}
+
+// What to do when an element ends
+enum Close {
+    Nothing,
+    Block,
+    Inline(&'static str),
+    Link(String),
+    List,
+    Item,
+    Quote,
+    Code,
+    Skip,
+    Table,
+}
+
+// Writes ENML, Evernote's XHTML dialect, as markdown
+struct Enml<'a> {
+    media: &'a HashMap<String, Media>,
+    out: String,
+    // The line being written, without its quote and list prefix
+    line: String,
+    // A list marker waiting for the item's first text
+    marker: Option<String>,
+    blank: bool,
+    quotes: usize,
+    // The next number of each open list, or `None` for bullets
+    lists: Vec<Option<usize>>,
+    code: Option<String>,
+    inline_code: usize,
+    skip: usize,
+    tables: usize,
+    rows: Vec<Vec<String>>,
+    closes: Vec<Close>,
+    warnings: Vec<String>,
+}
+
+impl<'a> Enml<'a> {
+    fn new(media: &'a HashMap<String, Media>) -> Self {
+        Self {
+            media,
+            out: String::new(),
+            line: String::new(),
+            marker: None,
+            blank: true,
+            quotes: 0,
+            lists: vec![],
+            code: None,
+            inline_code: 0,
+            skip: 0,
+            tables: 0,
+            rows: vec![],
+            closes: vec![],
+            warnings: vec![],
+        }
+    }
+
+    // Text goes to the current table cell while in a table
+    fn target(&mut self) -> &mut String {
+        if self.tables > 0 {
+            if let Some(cell) = self.rows.last_mut().and_then(|row| row.last_mut()) {
+                return cell;
+            }
+        }
+        &mut self.line
+    }
+
+    fn push(&mut self, text: &str) {
+        self.target().push_str(text);
+    }
+
+    fn space(&mut self) {
+        let target = self.target();
+        if !target.is_empty() && !target.ends_with(' ') {
+            target.push(' ');
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.skip > 0 {
+            return;
+        }
+        if let Some(code) = self.code.as_mut() {
+            return code.push_str(text);
+        }
+        for c in text.chars() {
+            match c {
+                c if c.is_whitespace() && c != '\u{a0}' => self.space(),
+                '\\' | '`' | '*' | '_' | '[' | ']' | '<' if self.inline_code == 0 => {
+                    self.push(&format!("\\{}", c))
+                }
+                c => self.target().push(c),
+            }
+        }
+    }
+
+    fn prefix(&self) -> String {
+        let quotes = "> ".repeat(self.quotes);
+        match &self.marker {
+            Some(marker) => format!("{}{}", quotes, marker),
+            None => format!("{}{}", quotes, "    ".repeat(self.lists.len())),
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        let prefix = self.prefix();
+        match line.is_empty() {
+            true => self.out.push_str(prefix.trim_end()),
+            false => self.out.push_str(&format!("{}{}", prefix, line)),
+        }
+        self.out.push('\n');
+        self.marker = None;
+        self.blank = false;
+    }
+
+    // An item's marker is kept until the item has some text
+    fn end_line(&mut self) {
+        if self.tables > 0 {
+            return self.space();
+        }
+        let line = self.line.trim().to_string();
+        self.line.clear();
+        if !line.is_empty() {
+            self.write_line(&line);
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.end_line();
+        if self.tables == 0 && self.lists.is_empty() && !self.blank && !self.out.is_empty() {
+            self.out.push_str("> ".repeat(self.quotes).trim_end());
+            self.out.push('\n');
+            self.blank = true;
+        }
+    }
+
+    fn media(&mut self, element: &BytesStart) {
+        let hash = attribute(element, "hash").unwrap_or_default();
+        match self.media.get(&hash.to_lowercase()).cloned() {
+            Some(media) if media.mime.starts_with("image/") => {
+                self.push(&format!("![{}]({})", media.name, media.url))
+            }
+            Some(media) => self.push(&format!("[{}]({})", media.name, media.url)),
+            None => self.warnings.push(format!("Attachment {} not found", hash)),
+        }
+    }
+
+    fn start(&mut self, element: &BytesStart) -> Close {
+        let name = String::from_utf8_lossy(element.local_name().as_ref()).to_lowercase();
+        if self.skip > 0 {
+            self.skip += 1;
+            return Close::Skip;
+        }
+        if self.code.is_some() {
+            return match name.as_str() {
+                "br" => {
+                    self.text("\n");
+                    Close::Nothing
+                }
+                "div" | "p" => Close::Block,
+                _ => Close::Nothing,
+            };
+        }
+
+        match name.as_str() {
+            "en-crypt" => {
+                self.warnings
+                    .push("Encrypted text was not imported".to_string());
+                self.skip += 1;
+                Close::Skip
+            }
+            "head" | "title" | "script" | "style" => {
+                self.skip += 1;
+                Close::Skip
+            }
+            "pre" => {
+                self.end_block();
+                self.code = Some(String::new());
+                Close::Code
+            }
+            // Evernote's own code blocks are divs with a line per child div
+            "div"
+                if attribute(element, "style")
+                    .map(|style| style.contains("-en-codeblock"))
+                    .unwrap_or(false) =>
+            {
+                self.end_block();
+                self.code = Some(String::new());
+                Close::Code
+            }
+            "p" | "div" | "section" | "article" | "header" | "footer" | "center" | "dl" | "dt"
+            | "dd" => {
+                self.end_block();
+                Close::Block
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.end_block();
+                let level = name[1..].parse().unwrap_or(1);
+                self.push(&format!("{} ", "#".repeat(level)));
+                Close::Block
+            }
+            "br" => {
+                match self.line.trim().is_empty() || self.tables > 0 {
+                    true => self.end_line(),
+                    false => {
+                        self.line = format!("{}\\", self.line.trim_end());
+                        self.end_line();
+                    }
+                }
+                Close::Nothing
+            }
+            "hr" => {
+                self.end_block();
+                self.write_line("---");
+                self.end_block();
+                Close::Nothing
+            }
+            "b" | "strong" => {
+                self.push("**");
+                Close::Inline("**")
+            }
+            "i" | "em" => {
+                self.push("*");
+                Close::Inline("*")
+            }
+            "s" | "strike" | "del" => {
+                self.push("~~");
+                Close::Inline("~~")
+            }
+            "code" => {
+                self.push("`");
+                self.inline_code += 1;
+                Close::Inline("`")
+            }
+            "a" => match attribute(element, "href") {
+                Some(href) if !href.trim().is_empty() => {
+                    self.push("[");
+                    Close::Link(href.trim().replace(' ', "%20"))
+                }
+                _ => Close::Nothing,
+            },
+            "img" => {
+                let src = attribute(element, "src").unwrap_or_default();
+                let alt = attribute(element, "alt").unwrap_or_default();
+                if !src.is_empty() && !src.starts_with("data:") {
+                    self.push(&format!("![{}]({})", alt, src.replace(' ', "%20")));
+                }
+                Close::Nothing
+            }
+            "en-media" => {
+                self.media(element);
+                Close::Nothing
+            }
+            "en-todo" => {
+                let checked = attribute(element, "checked").as_deref() == Some("true");
+                let marker = match checked {
+                    true => "[x] ",
+                    false => "[ ] ",
+                };
+                if self.line.trim().is_empty() && self.marker.is_none() && self.tables == 0 {
+                    self.marker = Some("- ".to_string());
+                }
+                self.push(marker);
+                Close::Nothing
+            }
+            "ul" | "ol" => {
+                match self.lists.is_empty() {
+                    true => self.end_block(),
+                    false => self.end_line(),
+                }
+                self.lists.push(match name.as_str() {
+                    "ol" => Some(1),
+                    _ => None,
+                });
+                Close::List
+            }
+            "li" => {
+                self.end_line();
+                let indent = "    ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}{}. ", indent, *n - 1)
+                    }
+                    _ => format!("{}- ", indent),
+                };
+                self.marker = Some(marker);
+                Close::Item
+            }
+            "blockquote" => {
+                self.end_block();
+                self.quotes += 1;
+                Close::Quote
+            }
+            "table" => {
+                self.end_block();
+                self.tables += 1;
+                Close::Table
+            }
+            "tr" if self.tables == 1 => {
+                self.rows.push(vec![]);
+                Close::Nothing
+            }
+            "td" | "th" if self.tables == 1 => {
+                match self.rows.last_mut() {
+                    Some(row) => row.push(String::new()),
+                    None => self.rows.push(vec![String::new()]),
+                }
+                Close::Nothing
+            }
+            _ => Close::Nothing,
+        }
+    }
+
+    fn close(&mut self, close: Close) {
+        match close {
+            Close::Nothing => {}
+            Close::Block => match self.code.as_mut() {
+                Some(code) if !code.ends_with('\n') => code.push('\n'),
+                Some(_) => {}
+                None => self.end_block(),
+            },
+            Close::Inline(marker) => {
+                if marker == "`" {
+                    self.inline_code -= 1;
+                }
+                self.push(marker);
+            }
+            Close::Link(href) => self.push(&format!("]({})", href)),
+            Close::List => {
+                self.end_line();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.end_block();
+                }
+            }
+            Close::Item => self.end_line(),
+            Close::Quote => {
+                self.end_line();
+                // The quote's last blank line is written outside it instead
+                if self.blank && !self.out.is_empty() {
+                    let end = self.out[..self.out.len() - 1].rfind('\n');
+                    self.out.truncate(end.map(|i| i + 1).unwrap_or(0));
+                    self.blank = false;
+                }
+                self.quotes -= 1;
+                self.end_block();
+            }
+            Close::Code => {
+                let code = self.code.take().unwrap_or_default();
+                let fence = match code.contains("```") {
+                    true => "~~~",
+                    false => "```",
+                };
+                self.write_line(fence);
+                for line in code.trim_end_matches('\n').lines() {
+                    self.write_line(line);
+                }
+                self.write_line(fence);
+                self.end_block();
+            }
+            Close::Skip => self.skip -= 1,
+            Close::Table => {
+                self.tables -= 1;
+                if self.tables == 0 {
+                    self.table();
+                }
+            }
+        }
+    }
+
+    // The first row is taken as the header, as markdown tables need one
+    fn table(&mut self) {
+        let rows = std::mem::take(&mut self.rows);
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        for (i, row) in rows.iter().enumerate() {
+            let cells: Vec<String> = (0..columns)
+                .map(|c| match row.get(c) {
+                    Some(cell) => cell.trim().replace('|', "\\|"),
+                    None => String::new(),
+                })
+                .collect();
+            self.write_line(&format!("| {} |", cells.join(" | ")));
+            if i == 0 {
+                self.write_line(&format!("|{}", " --- |".repeat(columns)));
+            }
+        }
+        self.end_block();
+    }
+
+    fn convert(mut self, content: &str) -> Result<(String, Vec<String>), String> {
+        let mut reader = Reader::from_str(content);
+        reader.check_end_names(false);
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(element)) => {
+                    let close = self.start(&element);
+                    self.closes.push(close);
+                }
+                Ok(Event::Empty(element)) => {
+                    let close = self.start(&element);
+                    self.close(close);
+                }
+                Ok(Event::End(_)) => {
+                    if let Some(close) = self.closes.pop() {
+                        self.close(close);
+                    }
+                }
+                Ok(Event::Text(text)) => self.text(&unescape(&text)),
+                Ok(Event::CData(text)) => self.text(&String::from_utf8_lossy(&text)),
+                Ok(Event::Eof) => break,
+                Ok(_) => {}
+                Err(e) => throw!(
+                    "Could not read note content at byte {}: {}",
+                    reader.buffer_position(),
+                    e
+                ),
+            }
+        }
+        self.end_block();
+        Ok((self.out.trim_end().to_string() + "\n", self.warnings))
+    }
+}
+
+// Turn a note read from the export into one for this app
+fn convert(note: EnexNote, path: &PathBuf, report: &mut ImportReport) -> Option<Note> {
+    let (body, warnings) = match Enml::new(&note.media).convert(&note.content) {
+        Ok(converted) => converted,
+        Err(e) => {
+            report.fail(path, &format!("\"{}\": {}", note.title, e));
+            return None;
+        }
+    };
+    for warning in warnings {
+        report.warn(path, &format!("\"{}\": {}", note.title, warning));
+    }
+
+    let mut content = Note::new(&note.title, &body);
+    content.tags = note.tags;
+    content.created_at = note.created.unwrap_or_else(now_millis);
+    content.modified_at = note.updated.unwrap_or(content.created_at);
+    Some(content)
+}
+
+// Import an Evernote export. The file is read a note at a time so exports
+// of any size fit in memory, then every note is saved in one batch. Notes go
+// to a notebook named after the file, as Evernote exports a notebook per file
+pub fn import_file<B: Backend>(
+    path: &PathBuf,
+    notes: &mut Notes<B>,
+    notebooks: &mut Notebooks,
+    progress: &mut dyn FnMut(&ImportProgress),
+) -> Result<ImportReport, String> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => throw!("Could not open {}: {}", path.display(), e),
+    };
+    let total_bytes = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut reader = Reader::from_reader(BufReader::new(file));
+    let mut report = ImportReport::default();
+
+    let mut imported = vec![];
+    let mut buf = vec![];
+    let mut elements: Vec<String> = vec![];
+    let mut text = String::new();
+    let mut note: Option<EnexNote> = None;
+    let mut resource: Option<Resource> = None;
+    loop {
+        let event = match reader.read_event_into(&mut buf) {
+            Ok(event) => event,
+            Err(e) => throw!(
+                "Could not read {} at byte {}: {}",
+                path.display(),
+                reader.buffer_position(),
+                e
+            ),
+        };
+        match event {
+            Event::Start(element) => {
+                let name = String::from_utf8_lossy(element.local_name().as_ref()).to_string();
+                match name.as_str() {
+                    "note" => note = Some(EnexNote::default()),
+                    "resource" => resource = Some(Resource::default()),
+                    _ => {}
+                }
+                elements.push(name);
+                text.clear();
+            }
+            Event::Text(content) => text.push_str(&unescape(&content)),
+            Event::CData(content) => text.push_str(&String::from_utf8_lossy(&content)),
+            Event::End(_) => {
+                let name = elements.pop().unwrap_or_default();
+                let parent = elements.last().map(|p| p.as_str()).unwrap_or("");
+                let value = std::mem::take(&mut text);
+                match (parent, name.as_str(), note.as_mut(), resource.as_mut()) {
+                    (_, "data", _, Some(resource)) => resource.data = value,
+                    (_, "mime", _, Some(resource)) => resource.mime = value.trim().to_string(),
+                    (_, "file-name", _, Some(resource)) => {
+                        resource.file_name = Some(value.trim().to_string())
+                    }
+                    (_, "resource", Some(note), Some(_)) if notes.backend.encrypted() => {
+                        report.warn(path, &format!("\"{}\": {}", note.title, VAULT_ATTACHMENT))
+                    }
+                    (_, "resource", Some(note), Some(resource)) => {
+                        match store_resource(&notes.data_path, resource) {
+                            Ok((hash, media)) => {
+                                report.attachments += 1;
+                                note.media.insert(hash, media);
+                            }
+                            Err(e) => report.warn(path, &format!("\"{}\": {}", note.title, e)),
+                        }
+                    }
+                    ("note", "title", Some(note), None) => note.title = value.trim().to_string(),
+                    ("note", "content", Some(note), None) => note.content = value,
+                    ("note", "created", Some(note), None) => note.created = parse_date(&value),
+                    ("note", "updated", Some(note), None) => note.updated = parse_date(&value),
+                    ("note", "tag", Some(note), None) => note.tags.push(value.trim().to_string()),
+                    _ => {}
+                }
+                match name.as_str() {
+                    "resource" => resource = None,
+                    "note" => {
+                        if let Some(content) =
+                            note.take().and_then(|n| convert(n, path, &mut report))
+                        {
+                            imported.push(content);
+                            progress(&ImportProgress {
+                                source: path.to_path_buf(),
+                                title: imported[imported.len() - 1].title.to_owned(),
+                                notes: imported.len(),
+                                bytes_read: reader.buffer_position() as u64,
+                                total_bytes,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let parent = match name.trim().is_empty() || imported.is_empty() {
+        true => None,
+        false => match notebooks.find(name, None) {
+            Some(id) => Some(id),
+            None => {
+                report.notebooks += 1;
+                Some(notebooks.create(name, None)?.id)
+            }
+        },
+    };
+    for note in imported.iter_mut() {
+        note.parent = parent;
+    }
+
+    let uuids = notes.create_many(&imported)?;
+    for (uuid, note) in uuids.into_iter().zip(imported) {
+        report.imported.push(ImportedNote {
+            source: path.to_path_buf(),
+            uuid,
+            title: note.title,
+        });
+    }
+    Ok(report)
+}
+
+#[tauri::command]
+pub fn import_enex(path: PathBuf, window: Window, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    let report = cache.import_enex(&path, &mut |progress| {
+        if let Err(e) = window.emit("import-progress", progress) {
+            eprintln!("Error sending import progress: {}", e);
+        }
+    })?;
+
+    to_json(&report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_markdown(content: &str) -> String {
+        Enml::new(&HashMap::new()).convert(content).unwrap().0
+    }
+
+    fn media() -> HashMap<String, Media> {
+        let mut media = HashMap::new();
+        media.insert(
+            "0cc175b9c0f1b6a831c399e269772661".to_string(),
+            Media {
+                url: "attachments/cat.png".to_string(),
+                name: "cat.png".to_string(),
+                mime: "image/png".to_string(),
+            },
+        );
+        media.insert(
+            "92eb5ffee6ae2fec3ad71c777531578f".to_string(),
+            Media {
+                url: "attachments/report.pdf".to_string(),
+                name: "report.pdf".to_string(),
+                mime: "application/pdf".to_string(),
+            },
+        );
+        media
+    }
+
+    #[test]
+    fn converts_lists() {
+        assert_eq!(
+            to_markdown("<en-note><ul><li>one</li><li>two</li></ul></en-note>"),
+            "- one\n- two\n"
+        );
+        assert_eq!(
+            to_markdown("<en-note><ol><li>first</li><li>second</li><li>third</li></ol></en-note>"),
+            "1. first\n2. second\n3. third\n"
+        );
+        assert_eq!(
+            to_markdown(
+                "<en-note><ul><li>a<ol><li>a1</li><li>a2</li></ol></li><li>b</li></ul><p>after</p></en-note>"
+            ),
+            "- a\n    1. a1\n    2. a2\n- b\n\nafter\n"
+        );
+    }
+
+    #[test]
+    fn converts_todos_to_checkboxes() {
+        assert_eq!(
+            to_markdown(
+                "<en-note><div><en-todo checked=\"true\"/>Buy milk</div><div><en-todo checked=\"false\"/>Call Bob</div><div><en-todo/>Plan</div></en-note>"
+            ),
+            "- [x] Buy milk\n\n- [ ] Call Bob\n\n- [ ] Plan\n"
+        );
+        assert_eq!(
+            to_markdown("<en-note><ul><li><en-todo checked=\"true\"/>done</li><li><en-todo/>open</li></ul></en-note>"),
+            "- [x] done\n- [ ] open\n"
+        );
+    }
+
+    #[test]
+    fn converts_media_to_attachment_links() {
+        let media = media();
+        let (markdown, warnings) = Enml::new(&media)
+            .convert(
+                "<en-note><div>See <en-media type=\"image/png\" hash=\"0CC175B9C0F1B6A831C399E269772661\"/></div><div><en-media type=\"application/pdf\" hash=\"92eb5ffee6ae2fec3ad71c777531578f\"/></div><en-media type=\"image/png\" hash=\"ffff\"/></en-note>",
+            )
+            .unwrap();
+        assert_eq!(
+            markdown,
+            "See ![cat.png](attachments/cat.png)\n\n[report.pdf](attachments/report.pdf)\n"
+        );
+        assert_eq!(warnings, vec!["Attachment ffff not found".to_string()]);
+    }
+
+    #[test]
+    fn decodes_html_entities() {
+        assert_eq!(
+            to_markdown(
+                "<en-note><div>Fish &amp; chips &mdash; &ldquo;5 &lt; 6&rdquo;&hellip; &euro;3 &#169; &#x263A;</div></en-note>"
+            ),
+            "Fish & chips — “5 \\< 6”… €3 © ☺\n"
+        );
+        // A non-breaking space is kept rather than collapsed like other whitespace
+        assert_eq!(
+            to_markdown("<en-note><div>a&nbsp;&nbsp;b</div></en-note>"),
+            "a\u{a0}\u{a0}b\n"
+        );
+        // Entities ENML doesn't define are kept as written
+        assert_eq!(
+            to_markdown("<en-note><div>&unknown; x</div></en-note>"),
+            "&unknown; x\n"
+        );
+        assert_eq!(
+            to_markdown("<en-note><a href=\"https://example.com/?a=1&amp;b=2\">link</a></en-note>"),
+            "[link](https://example.com/?a=1&b=2)\n"
+        );
+    }
+}
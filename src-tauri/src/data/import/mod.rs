@@ -0,0 +1,106 @@
+pub mod enex;
+pub mod obsidian;
+
+use crate::core::utils::fs::ensure_parent_exists;
+use crate::data::markdown::slugify;
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// Files referenced by imported notes are kept here, inside the data directory
+pub const ATTACHMENTS_DIR: &str = "attachments";
+
+// Attachments are copied as is, so they would sit unencrypted next to the
+// vault's notes. Imports skip them while the vault is enabled
+pub const VAULT_ATTACHMENT: &str = "Attachments can't be imported into an encrypted vault, skipped";
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ImportedNote {
+    pub source: PathBuf,
+    pub uuid: Uuid,
+    pub title: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ImportIssue {
+    pub source: PathBuf,
+    pub error: String,
+}
+
+// Sent to the frontend as `import-progress` while a long import runs
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ImportProgress {
+    pub source: PathBuf,
+    // The note just read and how many have been read so far
+    pub title: String,
+    pub notes: usize,
+    pub bytes_read: u64,
+    pub total_bytes: u64,
+}
+
+// What an import did. `failed` lists what was not imported at all, `warnings`
+// what was imported with something missing, like an attachment, and
+// `skipped` notes that were already there
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+pub struct ImportReport {
+    pub imported: Vec<ImportedNote>,
+    pub skipped: Vec<ImportedNote>,
+    pub notebooks: usize,
+    pub attachments: usize,
+    pub failed: Vec<ImportIssue>,
+    pub warnings: Vec<ImportIssue>,
+}
+
+impl ImportReport {
+    pub fn fail(&mut self, source: &PathBuf, error: &str) {
+        self.failed.push(ImportIssue {
+            source: source.to_path_buf(),
+            error: error.to_string(),
+        });
+    }
+
+    pub fn warn(&mut self, source: &PathBuf, error: &str) {
+        self.warnings.push(ImportIssue {
+            source: source.to_path_buf(),
+            error: error.to_string(),
+        });
+    }
+}
+
+// Write an attachment into the data directory, returning the path notes link
+// it by. A different file that already has the name gets a numeric suffix
+pub fn store_attachment(data_path: &PathBuf, name: &str, bytes: &[u8]) -> Result<String, String> {
+    let name = PathBuf::from(name);
+    // Slugged so the name needs no escaping in a link
+    let stem = match name.file_stem().and_then(|s| s.to_str()) {
+        Some(stem) if !stem.trim().is_empty() => slugify(stem),
+        _ => "attachment".to_string(),
+    };
+    let extension = match name.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!(".{}", slugify(extension)),
+        None => String::new(),
+    };
+
+    let mut n = 0;
+    loop {
+        let file_name = match n {
+            0 => format!("{}{}", stem, extension),
+            n => format!("{}-{}{}", stem, n, extension),
+        };
+        let path = data_path.join(ATTACHMENTS_DIR).join(&file_name);
+        let url = format!("{}/{}", ATTACHMENTS_DIR, file_name);
+        match std::fs::read(&path) {
+            // Importing the same file twice links to the one copy
+            Ok(existing) if existing == bytes => return Ok(url),
+            Ok(_) => n += 1,
+            Err(_) => {
+                ensure_parent_exists(&path)?;
+                if let Err(e) = std::fs::write(&path, bytes) {
+                    throw!("Error writing {}: {}", path.display(), e);
+                }
+                return Ok(url);
+            }
+        }
+    }
+}
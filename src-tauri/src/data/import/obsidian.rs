@@ -0,0 +1,423 @@
+use crate::core::utils::json::to_json;
+use crate::data::backend::Backend;
+use crate::data::import::{store_attachment, ImportReport, ImportedNote, VAULT_ATTACHMENT};
+use crate::data::links::{percent_decode, scan, LinkTarget};
+use crate::data::markdown::split_front_matter;
+use crate::data::note::Note;
+use crate::data::notebooks::Notebooks;
+use crate::data::{Data, Notes};
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use tauri::State;
+
+// Everything in a vault worth importing. Attachments are looked up by file
+// name, the way Obsidian resolves `![[image.png]]`
+#[derive(Debug, Default)]
+struct VaultFiles {
+    notes: Vec<PathBuf>,
+    attachments: HashMap<String, PathBuf>,
+}
+
+// Hidden folders hold Obsidian's own state (`.obsidian`) and its trash (`.trash`)
+fn walk(dir: &PathBuf, files: &mut VaultFiles, report: &mut ImportReport) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return report.fail(dir, &format!("Could not read folder: {}", e)),
+    };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+    for path in paths {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            walk(&path, files, report);
+        } else if is_markdown_name(&name) {
+            files.notes.push(path);
+        } else {
+            files.attachments.entry(name.to_lowercase()).or_insert(path);
+        }
+    }
+}
+
+// Tags from front matter, written either as a list or as a comma or space separated string
+fn front_matter_tags(yaml: &serde_yaml::Value) -> Vec<String> {
+    let mut tags = vec![];
+    for key in ["tags", "tag"] {
+        match yaml.get(key) {
+            Some(serde_yaml::Value::Sequence(list)) => tags.extend(
+                list.iter()
+                    .filter_map(|t| t.as_str())
+                    .map(|t| t.to_string()),
+            ),
+            Some(serde_yaml::Value::String(list)) => tags.extend(
+                list.split(|c: char| c == ',' || c.is_whitespace())
+                    .map(|t| t.to_string()),
+            ),
+            _ => {}
+        }
+    }
+    tags
+}
+
+// `#tags` written in the body. A tag starts after whitespace and needs more than digits,
+// so headings and issue numbers like `#12` are skipped
+fn inline_tags(body: &str) -> Vec<String> {
+    let mut tags = vec![];
+    let mut in_code = false;
+    for line in body.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        let mut in_inline_code = false;
+        let mut previous = ' ';
+        for (i, c) in line.char_indices() {
+            if c == '`' {
+                in_inline_code = !in_inline_code;
+            } else if c == '#' && !in_inline_code && previous.is_whitespace() {
+                let tag: String = line[i + 1..]
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || "_-/".contains(*c))
+                    .collect();
+                if tag.chars().any(|c| !c.is_numeric()) {
+                    tags.push(tag);
+                }
+            }
+            previous = c;
+        }
+    }
+    tags
+}
+
+// A file name without its `.md` extension, in any case. Cut from the name
+// itself, since lowercasing can change the length of the text before it
+fn strip_markdown_extension(name: &str) -> Option<&str> {
+    let stem_len = name.len().checked_sub(3)?;
+    match name.get(stem_len..) {
+        Some(extension) if extension.eq_ignore_ascii_case(".md") => Some(&name[..stem_len]),
+        _ => None,
+    }
+}
+
+fn is_markdown_name(name: &str) -> bool {
+    strip_markdown_extension(name).is_some()
+}
+
+// The title a link to a note file refers to: its file name without `.md`
+fn note_title(target: &str) -> String {
+    let name = target.rsplit('/').next().unwrap_or(target);
+    strip_markdown_extension(name).unwrap_or(name).to_string()
+}
+
+struct Converter<'a> {
+    vault_dir: &'a PathBuf,
+    data_path: &'a PathBuf,
+    files: &'a VaultFiles,
+    encrypted: bool,
+    // Attachments already copied, by their path in the vault
+    stored: HashMap<PathBuf, String>,
+}
+
+impl<'a> Converter<'a> {
+    fn attachment(
+        &mut self,
+        path: &PathBuf,
+        source: &PathBuf,
+        report: &mut ImportReport,
+    ) -> Option<String> {
+        if let Some(url) = self.stored.get(path) {
+            return Some(url.to_owned());
+        }
+        if self.encrypted {
+            report.warn(source, &format!("{}: {}", path.display(), VAULT_ATTACHMENT));
+            return None;
+        }
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let stored = std::fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| store_attachment(self.data_path, name, &bytes));
+        match stored {
+            Ok(url) => {
+                report.attachments += 1;
+                self.stored.insert(path.to_path_buf(), url.to_owned());
+                Some(url)
+            }
+            Err(e) => {
+                let error = format!("Could not copy {}: {}", path.display(), e);
+                report.warn(source, &error);
+                None
+            }
+        }
+    }
+
+    // Obsidian resolves a relative path from the note's folder or, failing
+    // that, from the vault root. Files outside the vault are not copied
+    fn local_file(&self, destination: &str, source: &PathBuf) -> Option<PathBuf> {
+        let relative = PathBuf::from(percent_decode(destination));
+        let note_dir = source.parent().unwrap_or(self.vault_dir);
+        let vault_dir = self.vault_dir.canonicalize().ok()?;
+        [note_dir.join(&relative), self.vault_dir.join(&relative)]
+            .into_iter()
+            .filter_map(|path| path.canonicalize().ok())
+            .find(|path| path.is_file() && path.starts_with(&vault_dir))
+    }
+
+    // Rewrite the body for this app: wiki links lose their folder, embedded
+    // notes become links and attachments are copied into the data directory
+    fn convert(&mut self, body: &str, source: &PathBuf, report: &mut ImportReport) -> String {
+        let mut edits: Vec<(Range<usize>, String)> = vec![];
+
+        for link in scan(body) {
+            let target = match &link.target {
+                LinkTarget::Title(target) => target.to_owned(),
+                _ => continue,
+            };
+            let embedded = body[..link.outer.start].ends_with('!');
+            let outer = match embedded {
+                true => link.outer.start - 1..link.outer.end,
+                false => link.outer.to_owned(),
+            };
+            let name = target.rsplit('/').next().unwrap_or(&target).to_string();
+
+            // Anything that isn't a file in the vault is taken to be a note
+            let attachment = match is_markdown_name(&name) {
+                true => None,
+                false => self.files.attachments.get(&name.to_lowercase()).cloned(),
+            };
+            if let Some(path) = attachment {
+                if let Some(url) = self.attachment(&path, source, report) {
+                    let replacement = match embedded {
+                        true => format!("![{}]({})", name, url),
+                        false => format!("[{}]({})", link.label.unwrap_or(name), url),
+                    };
+                    edits.push((outer, replacement));
+                }
+                continue;
+            }
+
+            let mut replacement = format!("[[{}", note_title(&target));
+            if let Some(heading) = &link.heading {
+                replacement.push_str(&format!("#{}", heading));
+            }
+            if let Some(label) = &link.label {
+                replacement.push_str(&format!("|{}", label));
+            }
+            replacement.push_str("]]");
+            edits.push((outer, replacement));
+        }
+
+        let options = Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES;
+        for (event, range) in Parser::new_ext(body, options).into_offset_iter() {
+            let (is_image, destination) = match event {
+                Event::Start(Tag::Image(_, destination, _)) => (true, destination),
+                Event::Start(Tag::Link(_, destination, _)) => (false, destination),
+                _ => continue,
+            };
+            if destination.is_empty() || destination.starts_with('#') || destination.contains(':') {
+                continue;
+            }
+            let markdown = &body[range.to_owned()];
+            let (prefix, rest) = match is_image {
+                true => ("![", markdown.strip_prefix("![")),
+                false => ("[", markdown.strip_prefix('[')),
+            };
+            let (text, raw) = match rest.and_then(|rest| rest.rsplit_once("](")) {
+                Some((text, raw)) => (text, raw),
+                // Reference style links are left as they are
+                None => continue,
+            };
+
+            let path = destination.split('#').next().unwrap_or("");
+            if is_markdown_name(path) && !is_image {
+                let title = note_title(&percent_decode(path));
+                edits.push((range, format!("[[{}|{}]]", title, text)));
+                continue;
+            }
+            let url = match self.local_file(path, source) {
+                Some(file) => self.attachment(&file, source, report),
+                None => {
+                    report.warn(source, &format!("Attachment {} not found", destination));
+                    None
+                }
+            };
+            if let Some(url) = url {
+                let rest = raw.replacen(path, &url, 1);
+                edits.push((range, format!("{}{}]({}", prefix, text, rest)));
+            }
+        }
+
+        edits.sort_by_key(|(range, _)| range.start);
+        let mut result = String::new();
+        let mut last = 0;
+        for (range, replacement) in edits {
+            // A link can't be rewritten twice
+            if range.start < last {
+                continue;
+            }
+            result.push_str(&body[last..range.start]);
+            result.push_str(&replacement);
+            last = range.end;
+        }
+        result.push_str(&body[last..]);
+        result
+    }
+}
+
+// The notebook for a folder in the vault, creating it and its parents as needed.
+// Existing notebooks with the same name are reused, so importing twice
+// doesn't duplicate the hierarchy
+fn notebook_for(
+    folder: &PathBuf,
+    notebooks: &mut Notebooks,
+    cache: &mut HashMap<PathBuf, uuid::Uuid>,
+    report: &mut ImportReport,
+) -> Result<Option<uuid::Uuid>, String> {
+    let mut parent = None;
+    let mut path = PathBuf::new();
+    for component in folder.iter() {
+        path.push(component);
+        if let Some(id) = cache.get(&path) {
+            parent = Some(*id);
+            continue;
+        }
+        let name = component.to_string_lossy();
+        let id = match notebooks.find(&name, parent) {
+            Some(id) => id,
+            None => {
+                report.notebooks += 1;
+                notebooks.create(&name, parent)?.id
+            }
+        };
+        cache.insert(path.to_owned(), id);
+        parent = Some(id);
+    }
+    Ok(parent)
+}
+
+// Import every markdown file in an Obsidian vault. Files that can't be read
+// or parsed are skipped and listed in the report
+pub fn import_vault<B: Backend>(
+    vault_dir: &PathBuf,
+    notes: &mut Notes<B>,
+    notebooks: &mut Notebooks,
+) -> Result<ImportReport, String> {
+    if !vault_dir.is_dir() {
+        throw!("{} is not a folder", vault_dir.display());
+    }
+    let mut report = ImportReport::default();
+    let mut files = VaultFiles::default();
+    walk(vault_dir, &mut files, &mut report);
+
+    let data_path = notes.data_path.to_path_buf();
+    let mut converter = Converter {
+        vault_dir,
+        data_path: &data_path,
+        files: &files,
+        encrypted: notes.backend.encrypted(),
+        stored: HashMap::new(),
+    };
+    let mut folders = HashMap::new();
+    for path in files.notes.iter() {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                report.fail(path, &e.to_string());
+                continue;
+            }
+        };
+        let (yaml, body) = split_front_matter(&text);
+        let front_matter = match yaml {
+            Some(yaml) if !yaml.trim().is_empty() => {
+                match serde_yaml::from_str::<serde_yaml::Value>(yaml) {
+                    Ok(front_matter) => front_matter,
+                    Err(e) => {
+                        report.fail(path, &format!("Could not parse front matter: {}", e));
+                        continue;
+                    }
+                }
+            }
+            _ => serde_yaml::Value::Null,
+        };
+
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let title = match front_matter.get("title").and_then(|t| t.as_str()) {
+            Some(title) if !title.trim().is_empty() => title.trim().to_string(),
+            _ => stem.to_string(),
+        };
+        let mut tags = front_matter_tags(&front_matter);
+        tags.extend(inline_tags(body));
+
+        let body = converter.convert(body.trim_start_matches(['\r', '\n']), path, &mut report);
+
+        let folder = match path.parent().and_then(|p| p.strip_prefix(vault_dir).ok()) {
+            Some(folder) => folder.to_path_buf(),
+            None => PathBuf::new(),
+        };
+        let parent = match notebook_for(&folder, notebooks, &mut folders, &mut report) {
+            Ok(parent) => parent,
+            Err(e) => {
+                report.fail(path, &e);
+                continue;
+            }
+        };
+
+        let mut note = Note::new(&title, &body);
+        note.tags = tags;
+        note.parent = parent;
+        match notes.create(&note) {
+            Ok(uuid) => report.imported.push(ImportedNote {
+                source: path.to_path_buf(),
+                uuid,
+                title,
+            }),
+            Err(e) => report.fail(path, &e),
+        }
+    }
+    Ok(report)
+}
+
+#[tauri::command]
+pub fn import_obsidian(vault_dir: PathBuf, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.import_obsidian(&vault_dir)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_title_strips_the_extension_in_any_case() {
+        assert_eq!(note_title("Ideas.md"), "Ideas");
+        assert_eq!(note_title("folder/Ideas.MD"), "Ideas");
+        assert_eq!(note_title("Ideas"), "Ideas");
+        assert_eq!(note_title(".md"), "");
+        assert_eq!(note_title("md"), "md");
+    }
+
+    #[test]
+    fn note_title_keeps_non_ascii_names_whole() {
+        // The Kelvin sign lowercases to a shorter `k`
+        assert_eq!(note_title("ÄK.md"), "ÄK");
+        assert_eq!(note_title("Ünïcödé/Çafé.Md"), "Çafé");
+        assert_eq!(note_title("日本語.md"), "日本語");
+        assert_eq!(note_title("İstanbul"), "İstanbul");
+        assert_eq!(note_title("ö"), "ö");
+        assert!(is_markdown_name("ÄK.md"));
+        assert!(!is_markdown_name("日本語.mdx"));
+        assert!(!is_markdown_name("é"));
+    }
+}
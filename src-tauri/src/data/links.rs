@@ -0,0 +1,512 @@
+use crate::core::utils::json::to_json;
+use crate::data::error::NoteError;
+use crate::data::markdown::slugify;
+use crate::data::{Data, NoteFile};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use tauri::State;
+use uuid::Uuid;
+
+// What a link in a note body points at
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "kind", content = "target", rename_all = "camelCase")]
+pub enum LinkTarget {
+    // `[[Title]]`, `[[Title|label]]` or `[[Title#heading]]`
+    Title(String),
+    // `[label](<uuid>)` or `[label](note://<uuid>)`
+    Uuid(Uuid),
+    // `[label](other-note.md)`, matched against the file name of a markdown note
+    File(String),
+}
+
+// A link found in a note body. `span` is the part naming the target, which is
+// what gets rewritten when the target is renamed. For wiki links `outer` is
+// the whole `[[...]]`, with the `|label` and `#heading` parts split out
+#[derive(Debug, Clone)]
+pub struct RawLink {
+    pub target: LinkTarget,
+    pub span: Range<usize>,
+    pub outer: Range<usize>,
+    pub label: Option<String>,
+    pub heading: Option<String>,
+}
+
+// Titles are matched case-insensitively, ignoring surrounding whitespace
+fn normalize_title(title: &str) -> String {
+    title.trim().to_lowercase()
+}
+
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn same_target(a: &LinkTarget, b: &LinkTarget) -> bool {
+    match (a, b) {
+        (LinkTarget::Title(a), LinkTarget::Title(b)) => normalize_title(a) == normalize_title(b),
+        (LinkTarget::File(a), LinkTarget::File(b)) => a.eq_ignore_ascii_case(b),
+        (a, b) => a == b,
+    }
+}
+
+fn wiki_link(body: &str, start: usize, end: usize) -> Option<RawLink> {
+    let inner = &body[start..end];
+    if inner.contains('\n') {
+        return None;
+    }
+    let (target, label) = match inner.split_once('|') {
+        Some((target, label)) => (target, Some(label.trim().to_string())),
+        None => (inner, None),
+    };
+    let (title, heading) = match target.split_once('#') {
+        Some((title, heading)) => (title, Some(heading.trim().to_string())),
+        None => (target, None),
+    };
+    let trimmed = title.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    let offset = start + title.find(trimmed).unwrap_or(0);
+    Some(RawLink {
+        target: LinkTarget::Title(trimmed.to_string()),
+        span: offset..offset + trimmed.len(),
+        outer: start - 2..end + 2,
+        label: label.filter(|l| !l.is_empty()),
+        heading: heading.filter(|h| !h.is_empty()),
+    })
+}
+
+// The note a markdown link destination points at, if any
+pub fn parse_destination(destination: &str) -> Option<LinkTarget> {
+    let destination = destination.split('#').next().unwrap_or("");
+    let id = destination.strip_prefix("note://").unwrap_or(destination);
+    if let Ok(uuid) = Uuid::parse_str(id) {
+        return Some(LinkTarget::Uuid(uuid));
+    }
+    if destination.contains("://") || !destination.to_lowercase().ends_with(".md") {
+        return None;
+    }
+    let name_start = destination.rfind('/').map(|i| i + 1).unwrap_or(0);
+    Some(LinkTarget::File(percent_decode(&destination[name_start..])))
+}
+
+fn markdown_link(body: &str, start: usize, end: usize) -> Option<RawLink> {
+    let inner = &body[start..end];
+    // `<...>` allows spaces in the destination; otherwise it ends at the first space
+    let (start, destination) = match inner.strip_prefix('<') {
+        Some(rest) => (start + 1, rest.split('>').next().unwrap_or("")),
+        None => (start, inner.split_whitespace().next().unwrap_or("")),
+    };
+    let destination = destination.split('#').next().unwrap_or("");
+
+    let target = parse_destination(destination)?;
+    let name_start = match target {
+        LinkTarget::File(_) => destination.rfind('/').map(|i| i + 1).unwrap_or(0),
+        _ => 0,
+    };
+    Some(RawLink {
+        target,
+        span: start + name_start..start + destination.len(),
+        outer: start..start + destination.len(),
+        label: None,
+        heading: None,
+    })
+}
+
+// Find wiki links and markdown links to other notes, skipping fenced code blocks
+pub fn scan(body: &str) -> Vec<RawLink> {
+    let mut links = vec![];
+    let mut in_code = false;
+    let mut line_start = 0;
+    for line in body.split_inclusive('\n') {
+        let offset = line_start;
+        line_start += line.len();
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+
+        let mut pos = 0;
+        while pos < line.len() {
+            let rest = &line[pos..];
+            if let Some(inner) = rest.strip_prefix("[[") {
+                if let Some(len) = inner.find("]]") {
+                    let start = offset + pos + 2;
+                    links.extend(wiki_link(body, start, start + len));
+                    pos += len + 4;
+                    continue;
+                }
+            } else if let Some(inner) = rest.strip_prefix("](") {
+                if let Some(len) = inner.find(')') {
+                    let start = offset + pos + 2;
+                    links.extend(markdown_link(body, start, start + len));
+                    pos += len + 3;
+                    continue;
+                }
+            }
+            pos += rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+        }
+    }
+    links
+}
+
+// Every target linked from a note body, in order and without duplicates
+pub fn parse_links(body: &str) -> Vec<LinkTarget> {
+    let mut targets: Vec<LinkTarget> = vec![];
+    for link in scan(body) {
+        if !targets.contains(&link.target) {
+            targets.push(link.target);
+        }
+    }
+    targets
+}
+
+// Point links at `from` to `to` instead. Returns `None` when nothing changed
+pub fn rewrite_links(body: &str, from: &LinkTarget, to: &str) -> Option<String> {
+    let mut result = String::new();
+    let mut last = 0;
+    for link in scan(body) {
+        if !same_target(&link.target, from) {
+            continue;
+        }
+        result.push_str(&body[last..link.span.start]);
+        match link.target {
+            // A bare destination can't hold spaces
+            LinkTarget::File(_) if !body[..link.span.start].ends_with('<') => {
+                result.push_str(&to.replace(' ', "%20"))
+            }
+            _ => result.push_str(to),
+        }
+        last = link.span.end;
+    }
+    match last {
+        0 => None,
+        _ => {
+            result.push_str(&body[last..]);
+            Some(result)
+        }
+    }
+}
+
+// Turn wiki links into markdown links to `note://<uuid>`, so they render as
+// links. Links to notes that don't exist are left as they are
+pub fn wiki_to_markdown(body: &str, index: &LinkIndex) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+    for link in scan(body) {
+        let title = match &link.target {
+            LinkTarget::Title(title) => title,
+            _ => continue,
+        };
+        let uuid = match index.resolve(&link.target) {
+            Some(uuid) => uuid,
+            None => continue,
+        };
+        let label = link.label.to_owned().unwrap_or_else(|| title.to_owned());
+        let fragment = match &link.heading {
+            Some(heading) => format!("#{}", slugify(heading)),
+            None => String::new(),
+        };
+        result.push_str(&body[last..link.outer.start]);
+        result.push_str(&format!(
+            "[{}](note://{}{})",
+            label.replace('[', "\\[").replace(']', "\\]"),
+            uuid,
+            fragment
+        ));
+        last = link.outer.end;
+    }
+    result.push_str(&body[last..]);
+    result
+}
+
+// The file name a markdown note is linked by
+pub fn file_name(note: &NoteFile) -> Option<String> {
+    match note.file_path.extension().and_then(|e| e.to_str()) {
+        Some("md") => note
+            .file_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.to_string()),
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct LinkedNote {
+    pub uuid: Uuid,
+    pub title: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct OutgoingLink {
+    pub target: LinkTarget,
+    // The note the link resolves to, or `None` for a broken link
+    pub note: Option<LinkedNote>,
+}
+
+// The links between notes. Each note's targets are kept as written and
+// resolved against the current titles and file names. Names and targets are
+// indexed both ways, so a change only re-resolves the notes linking to the
+// names involved
+#[derive(Debug, Clone, Default)]
+pub struct LinkIndex {
+    links: HashMap<Uuid, Vec<LinkTarget>>,
+    titles: HashMap<Uuid, String>,
+    files: HashMap<Uuid, String>,
+    by_title: HashMap<String, BTreeSet<Uuid>>,
+    by_file: HashMap<String, BTreeSet<Uuid>>,
+    // Notes linking to each target, keyed by `target_key`
+    sources: HashMap<LinkTarget, BTreeSet<Uuid>>,
+    // The notes each note's links currently resolve to
+    resolved: HashMap<Uuid, BTreeSet<Uuid>>,
+    backlinks: HashMap<Uuid, BTreeSet<Uuid>>,
+}
+
+// Targets that resolve the same way share a key
+fn target_key(target: &LinkTarget) -> LinkTarget {
+    match target {
+        LinkTarget::Title(title) => LinkTarget::Title(normalize_title(title)),
+        LinkTarget::File(name) => LinkTarget::File(name.to_ascii_lowercase()),
+        LinkTarget::Uuid(uuid) => LinkTarget::Uuid(*uuid),
+    }
+}
+
+fn add_to(map: &mut HashMap<String, BTreeSet<Uuid>>, key: &str, uuid: &Uuid) {
+    map.entry(key.to_string()).or_default().insert(*uuid);
+}
+
+fn remove_from(map: &mut HashMap<String, BTreeSet<Uuid>>, key: &str, uuid: &Uuid) {
+    if let Some(uuids) = map.get_mut(key) {
+        uuids.remove(uuid);
+        if uuids.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+impl LinkIndex {
+    // `entries` only need their headers, with `targets` giving what each body links to
+    pub fn new_from_entries(
+        entries: &HashMap<Uuid, NoteFile>,
+        targets: &dyn Fn(&Uuid) -> Vec<LinkTarget>,
+    ) -> Self {
+        let mut index = Self::default();
+        for (uuid, note) in entries.iter() {
+            index.set(uuid, note, targets(uuid));
+        }
+        let sources: Vec<Uuid> = index.links.keys().copied().collect();
+        for source in sources {
+            index.relink(&source);
+        }
+        index
+    }
+
+    // Record a note's names and targets, returning the names it went by before
+    fn set(
+        &mut self,
+        uuid: &Uuid,
+        note: &NoteFile,
+        targets: Vec<LinkTarget>,
+    ) -> (Option<String>, Option<String>) {
+        let (old_title, old_file) = self.unset(uuid);
+        for target in targets.iter() {
+            self.sources
+                .entry(target_key(target))
+                .or_default()
+                .insert(*uuid);
+        }
+        self.links.insert(*uuid, targets);
+        // Locked notes show a placeholder title, which links shouldn't find them by
+        if note.locked.is_none() {
+            let title = normalize_title(&note.content.title);
+            add_to(&mut self.by_title, &title, uuid);
+            self.titles.insert(*uuid, title);
+        }
+        if let Some(name) = file_name(note) {
+            add_to(&mut self.by_file, &name.to_ascii_lowercase(), uuid);
+            self.files.insert(*uuid, name);
+        }
+        (old_title, old_file)
+    }
+
+    // Forget a note's names and targets, returning the names it went by
+    fn unset(&mut self, uuid: &Uuid) -> (Option<String>, Option<String>) {
+        for target in self.links.remove(uuid).unwrap_or_default() {
+            let key = target_key(&target);
+            if let Some(sources) = self.sources.get_mut(&key) {
+                sources.remove(uuid);
+                if sources.is_empty() {
+                    self.sources.remove(&key);
+                }
+            }
+        }
+        let title = self.titles.remove(uuid);
+        if let Some(title) = &title {
+            remove_from(&mut self.by_title, title, uuid);
+        }
+        let file = self.files.remove(uuid);
+        if let Some(file) = &file {
+            remove_from(&mut self.by_file, &file.to_ascii_lowercase(), uuid);
+        }
+        (title, file)
+    }
+
+    // Notes whose links could resolve differently once a note takes or gives
+    // up these names
+    fn affected(
+        &self,
+        uuid: &Uuid,
+        titles: &[Option<String>],
+        files: &[Option<String>],
+    ) -> BTreeSet<Uuid> {
+        let mut keys = vec![LinkTarget::Uuid(*uuid)];
+        keys.extend(
+            titles
+                .iter()
+                .flatten()
+                .map(|t| LinkTarget::Title(t.to_owned())),
+        );
+        keys.extend(
+            files
+                .iter()
+                .flatten()
+                .map(|f| LinkTarget::File(f.to_ascii_lowercase())),
+        );
+        keys.iter()
+            .filter_map(|key| self.sources.get(key))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    // Resolve a note's links again, moving its backlinks to match
+    fn relink(&mut self, source: &Uuid) {
+        for target in self.resolved.remove(source).unwrap_or_default() {
+            if let Some(sources) = self.backlinks.get_mut(&target) {
+                sources.remove(source);
+                if sources.is_empty() {
+                    self.backlinks.remove(&target);
+                }
+            }
+        }
+        let targets = self.resolve_all(source);
+        for target in targets.iter() {
+            self.backlinks.entry(*target).or_default().insert(*source);
+        }
+        if !targets.is_empty() {
+            self.resolved.insert(*source, targets);
+        }
+    }
+
+    pub fn update(&mut self, uuid: &Uuid, note: &NoteFile, targets: Vec<LinkTarget>) {
+        let is_new = !self.links.contains_key(uuid);
+        let (old_title, old_file) = self.set(uuid, note, targets);
+        let titles = [old_title, self.titles.get(uuid).cloned()];
+        let files = [old_file, self.files.get(uuid).cloned()];
+        let mut affected = match !is_new && titles[0] == titles[1] && files[0] == files[1] {
+            true => BTreeSet::new(),
+            false => self.affected(uuid, &titles, &files),
+        };
+        affected.insert(*uuid);
+        for source in affected {
+            self.relink(&source);
+        }
+    }
+
+    pub fn remove(&mut self, uuid: &Uuid) {
+        let (title, file) = self.unset(uuid);
+        self.relink(uuid);
+        for source in self.affected(uuid, &[title], &[file]) {
+            self.relink(&source);
+        }
+    }
+
+    // The note a target points at. With several notes of the same title the
+    // oldest uuid wins, so the choice is at least stable
+    pub fn resolve(&self, target: &LinkTarget) -> Option<Uuid> {
+        match target_key(target) {
+            LinkTarget::Uuid(uuid) => self.links.get(&uuid).map(|_| uuid),
+            LinkTarget::Title(title) => self.by_title.get(&title)?.iter().next().copied(),
+            LinkTarget::File(name) => self.by_file.get(&name)?.iter().next().copied(),
+        }
+    }
+
+    fn resolve_all(&self, uuid: &Uuid) -> BTreeSet<Uuid> {
+        match self.links.get(uuid) {
+            Some(targets) => targets
+                .iter()
+                .filter_map(|t| self.resolve(t))
+                .filter(|t| t != uuid)
+                .collect(),
+            None => BTreeSet::new(),
+        }
+    }
+
+    pub fn outgoing(&self, uuid: &Uuid) -> Vec<(LinkTarget, Option<Uuid>)> {
+        match self.links.get(uuid) {
+            Some(targets) => targets
+                .iter()
+                .map(|t| (t.to_owned(), self.resolve(t)))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn backlinks(&self, uuid: &Uuid) -> Vec<Uuid> {
+        match self.backlinks.get(uuid) {
+            Some(sources) => sources.iter().copied().collect(),
+            None => vec![],
+        }
+    }
+
+    // Notes whose links point at `target`
+    pub fn linking_to(&self, target: &LinkTarget) -> Vec<Uuid> {
+        let sources = match self.sources.get(&target_key(target)) {
+            Some(sources) => sources,
+            None => return vec![],
+        };
+        sources
+            .iter()
+            .filter(|uuid| match self.links.get(uuid) {
+                Some(targets) => targets.iter().any(|t| same_target(t, target)),
+                None => false,
+            })
+            .copied()
+            .collect()
+    }
+}
+
+#[tauri::command]
+pub fn get_backlinks(uuid: Uuid, data: State<'_, Data>) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+
+    Ok(to_json(&cache.get_backlinks(&uuid)?)?)
+}
+
+#[tauri::command]
+pub fn get_outgoing_links(uuid: Uuid, data: State<'_, Data>) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+
+    Ok(to_json(&cache.get_outgoing_links(&uuid)?)?)
+}
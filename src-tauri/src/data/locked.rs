@@ -0,0 +1,105 @@
+use crate::core::utils::json::to_json;
+use crate::data::error::NoteError;
+use crate::data::note::Note;
+use crate::data::vault::{Cipher, Kdf, Sealed};
+use crate::data::Data;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::State;
+use uuid::Uuid;
+
+// Shown in place of the title of a locked note
+pub const LOCKED_TITLE: &str = "Locked note";
+
+// A note encrypted with its own password. `payload` is the sealed `Note`
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct LockedNote {
+    pub kdf: Kdf,
+    pub payload: Sealed,
+}
+
+impl LockedNote {
+    pub fn seal(note: &Note, password: &str) -> Result<(Self, UnlockedNote), String> {
+        if password.is_empty() {
+            throw!("The password cannot be empty");
+        }
+        let kdf = Kdf::generate();
+        let unlocked = UnlockedNote {
+            note: note.to_owned(),
+            kdf: kdf.to_owned(),
+            cipher: kdf.derive(password)?,
+        };
+        Ok((unlocked.seal(note)?, unlocked))
+    }
+
+    pub fn open(&self, password: &str) -> Result<UnlockedNote, String> {
+        let cipher = self.kdf.derive(password)?;
+        let plaintext = match cipher.open(&self.payload) {
+            Ok(plaintext) => plaintext,
+            Err(_) => throw!("Wrong password"),
+        };
+        match serde_json::from_slice(&plaintext) {
+            Ok(note) => Ok(UnlockedNote {
+                note,
+                kdf: self.kdf.to_owned(),
+                cipher,
+            }),
+            Err(e) => throw!("Could not parse decrypted note: {}", e),
+        }
+    }
+}
+
+// A locked note decrypted for this session. The key is kept so edits can be
+// saved encrypted without asking for the password again
+#[derive(Debug, Clone)]
+pub struct UnlockedNote {
+    pub note: Note,
+    kdf: Kdf,
+    cipher: Cipher,
+}
+
+impl UnlockedNote {
+    pub fn seal(&self, note: &Note) -> Result<LockedNote, String> {
+        Ok(LockedNote {
+            kdf: self.kdf.to_owned(),
+            payload: self.cipher.seal(to_json(note)?.to_string().as_bytes())?,
+        })
+    }
+}
+
+// What is kept readable of a locked note: where it lives and when it changed,
+// but nothing it says
+pub fn placeholder(note: &Note) -> Note {
+    Note {
+        title: LOCKED_TITLE.to_string(),
+        body: String::new(),
+        tags: vec![],
+        ..note.to_owned()
+    }
+}
+
+// Lock a note with a password, or with no password forget its decrypted
+// content so it has to be unlocked again
+#[tauri::command]
+pub fn lock_note(
+    uuid: Uuid,
+    password: Option<String>,
+    data: State<'_, Data>,
+) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+    cache.lock_note(&uuid, password.as_deref())?;
+
+    Ok(to_json(&cache.get(&uuid)?)?)
+}
+
+#[tauri::command]
+pub fn unlock_note(
+    uuid: Uuid,
+    password: String,
+    data: State<'_, Data>,
+) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+
+    Ok(to_json(&cache.unlock_note(&uuid, &password)?)?)
+}
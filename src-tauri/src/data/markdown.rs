@@ -0,0 +1,230 @@
+use crate::core::utils::time::{file_modified_millis, from_rfc3339, to_rfc3339};
+use crate::data::locked::LockedNote;
+use crate::data::note::{Note, NOTE_SCHEMA_VERSION};
+use crate::data::NoteFile;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+const MAX_SLUG_LENGTH: usize = 80;
+
+// The YAML block at the top of a markdown note
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+struct FrontMatter {
+    uuid: Option<Uuid>,
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<Uuid>,
+    created: Option<String>,
+    modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locked: Option<LockedNote>,
+}
+
+// Turn a title into a file name stem: lowercase words joined by dashes
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.trim().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.chars().count() >= MAX_SLUG_LENGTH {
+            break;
+        }
+    }
+    let slug = slug.trim_end_matches('-').to_string();
+    match slug.is_empty() {
+        true => "untitled".to_string(),
+        false => slug,
+    }
+}
+
+// A file name for every note, unique even when titles repeat
+pub fn file_names(notes: &[(Uuid, Note)], extension: &str) -> HashMap<Uuid, String> {
+    let mut names = HashMap::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (uuid, note) in notes.iter() {
+        let slug = slugify(&note.title);
+        let count = seen.entry(slug.to_owned()).or_default();
+        let name = match *count {
+            0 => format!("{}.{}", slug, extension),
+            n => format!("{}-{}.{}", slug, n, extension),
+        };
+        *count += 1;
+        names.insert(*uuid, name);
+    }
+    names
+}
+
+// Pick a file name for a note from its title. Names taken by other files get a
+// numeric suffix, and a note that already has a fitting name keeps it
+pub fn note_path(dir: &PathBuf, title: &str, current: Option<&PathBuf>) -> PathBuf {
+    let slug = slugify(title);
+    if let Some(current) = current {
+        if current.parent() == Some(dir.as_path())
+            && is_markdown(current)
+            && fits_slug(current, &slug)
+        {
+            return current.to_path_buf();
+        }
+    }
+
+    let mut n = 1;
+    loop {
+        let candidate = match n {
+            1 => dir.join(format!("{}.md", slug)),
+            _ => dir.join(format!("{}-{}.md", slug, n)),
+        };
+        if !candidate.exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+// Whether a file is named `<slug>.md` or `<slug>-<n>.md`
+fn fits_slug(path: &PathBuf, slug: &str) -> bool {
+    let stem = match path.file_stem().and_then(|s| s.to_str()) {
+        Some(stem) => stem,
+        None => return false,
+    };
+    match stem.strip_prefix(slug) {
+        Some("") => true,
+        Some(suffix) => match suffix.strip_prefix('-') {
+            Some(n) => !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()),
+            None => false,
+        },
+        None => false,
+    }
+}
+
+pub fn is_markdown(path: &PathBuf) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("md")
+}
+
+// Split a file into its front matter and body. Files without front matter are all body
+pub fn split_front_matter(text: &str) -> (Option<&str>, &str) {
+    let rest = match text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    {
+        Some(rest) => rest,
+        None => return (None, text),
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, text)
+}
+
+// Read a markdown note. The title falls back to the file name and the
+// timestamps to the file's modification time, so plain `.md` files load too
+pub fn load(path: &PathBuf) -> Result<NoteFile, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => throw!("{}", e.to_string()),
+    };
+    parse(&text, path, file_modified_millis(path))
+}
+
+// Read only a markdown note's front matter, leaving the body empty
+pub fn load_header(path: &PathBuf) -> Result<NoteFile, String> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => throw!("{}", e.to_string()),
+    };
+    let mut reader = BufReader::new(file);
+    let mut header = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => header.push_str(&line),
+            Err(e) => throw!("{}", e.to_string()),
+        }
+        // Stop at the closing `---`, or right away without front matter
+        let is_first = header.len() == line.len();
+        if (line.trim_end() == "---") != is_first {
+            break;
+        }
+    }
+    let text = match split_front_matter(&header) {
+        (Some(_), _) => header.as_str(),
+        (None, _) => "",
+    };
+    parse(text, path, file_modified_millis(path))
+}
+
+// Read a markdown note from `text`, as if loaded from `path`. Missing
+// timestamps fall back to `modified`
+pub fn parse(text: &str, path: &PathBuf, modified: u64) -> Result<NoteFile, String> {
+    let (yaml, body) = split_front_matter(text);
+    let front_matter: FrontMatter = match yaml {
+        Some(yaml) if !yaml.trim().is_empty() => match serde_yaml::from_str(yaml) {
+            Ok(front_matter) => front_matter,
+            Err(e) => throw!("Could not parse front matter: {}", e),
+        },
+        _ => FrontMatter::default(),
+    };
+
+    let title = match front_matter.title {
+        Some(title) => title,
+        None => path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+    let note = Note {
+        version: NOTE_SCHEMA_VERSION,
+        title,
+        body: body.to_string(),
+        tags: front_matter.tags,
+        parent: front_matter.parent,
+        created_at: front_matter
+            .created
+            .and_then(|c| from_rfc3339(&c))
+            .unwrap_or(modified),
+        modified_at: front_matter
+            .modified
+            .and_then(|m| from_rfc3339(&m))
+            .unwrap_or(modified),
+    };
+    Ok(NoteFile {
+        file_path: path.to_path_buf(),
+        uuid: front_matter.uuid,
+        content: note,
+        vault: None,
+        locked: front_matter.locked,
+    })
+}
+
+// Render a note as front matter followed by its markdown body
+pub fn render(note: &NoteFile) -> Result<String, String> {
+    let front_matter = FrontMatter {
+        uuid: note.uuid,
+        title: Some(note.content.title.to_owned()),
+        tags: note.content.tags.to_owned(),
+        parent: note.content.parent,
+        created: Some(to_rfc3339(note.content.created_at)),
+        modified: Some(to_rfc3339(note.content.modified_at)),
+        locked: note.locked.to_owned(),
+    };
+    let yaml = match serde_yaml::to_string(&front_matter) {
+        Ok(yaml) => yaml,
+        Err(e) => throw!("Error serializing front matter: {}", e),
+    };
+    Ok(format!("---\n{}---\n{}", yaml, note.content.body))
+}
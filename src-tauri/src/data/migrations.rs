@@ -0,0 +1,202 @@
+use crate::core::utils::fs::{copy_dir, write_atomically};
+use crate::core::utils::json::to_json;
+use crate::core::utils::time::{file_modified_millis, now_millis};
+use crate::data::markdown::is_markdown;
+use crate::data::note::Note;
+use crate::data::{is_note_path, Data, NoteFile};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::read_dir;
+use std::path::PathBuf;
+use tauri::State;
+use uuid::Uuid;
+
+// Version of the on-disk note file format written by `NoteFile::save`
+pub const CURRENT_FORMAT_VERSION: u32 = 2;
+// Copies of the data directory taken before migrating, in the app directory
+pub const MIGRATION_BACKUPS_DIR: &str = "migration-backups";
+
+// What every note file is wrapped in on disk
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct Envelope<T> {
+    pub format_version: u32,
+    pub data: T,
+}
+
+// A single upgrade step from `from` to `from + 1`, run on the unwrapped note JSON
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub run: fn(Value, &PathBuf) -> Result<Value, String>,
+}
+
+// Every migration, in the order they have to run
+pub fn registry() -> Vec<Migration> {
+    vec![
+        Migration {
+            from: 0,
+            description: "Parse the stringified `content` into a typed note",
+            run: typed_note_content,
+        },
+        Migration {
+            from: 1,
+            description: "Wrap note files in a versioned envelope",
+            run: |value, _| Ok(value),
+        },
+    ]
+}
+
+fn typed_note_content(mut value: Value, path: &PathBuf) -> Result<Value, String> {
+    let legacy = match value.get("content") {
+        Some(Value::String(content)) => content.to_owned(),
+        _ => return Ok(value),
+    };
+    let note = Note::from_legacy(&legacy, file_modified_millis(path));
+    value["content"] = to_json(&note)?;
+    Ok(value)
+}
+
+// Work out which format a parsed note file is in, returning it with the envelope removed.
+// Files from before the envelope existed are told apart by the type of `content`
+pub fn detect_version(value: Value) -> (u32, Value) {
+    if let Some(version) = value.get("format_version").and_then(|v| v.as_u64()) {
+        let data = match value {
+            Value::Object(mut map) => map.remove("data").unwrap_or(Value::Null),
+            _ => Value::Null,
+        };
+        return (version as u32, data);
+    }
+    match value.get("content") {
+        Some(Value::String(_)) => (0, value),
+        _ => (1, value),
+    }
+}
+
+// Run every migration the file needs, returning the upgraded note JSON and the version it started at
+pub fn upgrade(value: Value, path: &PathBuf) -> Result<(Value, u32), String> {
+    let (from_version, mut data) = detect_version(value);
+    if from_version > CURRENT_FORMAT_VERSION {
+        throw!(
+            "{} was written by a newer version of the app (format {})",
+            path.display(),
+            from_version
+        );
+    }
+    for migration in registry() {
+        if migration.from >= from_version {
+            data = match (migration.run)(data, path) {
+                Ok(data) => data,
+                Err(e) => throw!("{} ({})", e, migration.description),
+            };
+        }
+    }
+    Ok((data, from_version))
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct MigratedNote {
+    pub uuid: Option<Uuid>,
+    pub file_path: PathBuf,
+    pub from_version: u32,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct FailedMigration {
+    pub file_path: PathBuf,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+pub struct MigrationReport {
+    pub to_version: u32,
+    pub backup_path: Option<PathBuf>,
+    pub migrated: Vec<MigratedNote>,
+    pub failed: Vec<FailedMigration>,
+}
+
+// Upgrade every outdated note file in the data directory, backing the directory up first
+pub fn migrate_data_dir(
+    data_path: &PathBuf,
+    backup_root: &PathBuf,
+) -> Result<MigrationReport, String> {
+    let mut report = MigrationReport {
+        to_version: CURRENT_FORMAT_VERSION,
+        ..Default::default()
+    };
+    let entries = match read_dir(data_path) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(report),
+    };
+
+    let mut outdated = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // Markdown notes carry no envelope and are never migrated
+        if !is_note_path(&path) || is_markdown(&path) {
+            continue;
+        }
+        let value: Value = match std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+        {
+            Ok(value) => value,
+            Err(e) => {
+                report.failed.push(FailedMigration {
+                    file_path: path,
+                    error: e,
+                });
+                continue;
+            }
+        };
+        let is_current = value.get("format_version").and_then(|v| v.as_u64())
+            == Some(CURRENT_FORMAT_VERSION as u64);
+        if !is_current {
+            outdated.push((path, value));
+        }
+    }
+    if outdated.is_empty() {
+        return Ok(report);
+    }
+
+    let backup_path = backup_root.join(format!("pre-migration-{}", now_millis()));
+    copy_dir(data_path, &backup_path)?;
+    report.backup_path = Some(backup_path);
+
+    for (path, value) in outdated {
+        match migrate_file(&path, value) {
+            Ok(migrated) => report.migrated.push(migrated),
+            Err(error) => report.failed.push(FailedMigration {
+                file_path: path,
+                error,
+            }),
+        }
+    }
+    Ok(report)
+}
+
+fn migrate_file(path: &PathBuf, value: Value) -> Result<MigratedNote, String> {
+    let (data, from_version) = upgrade(value, path)?;
+    let note: NoteFile = match serde_json::from_value(data) {
+        Ok(note) => note,
+        Err(e) => throw!("Could not parse migrated note: {}", e),
+    };
+    let envelope = Envelope {
+        format_version: CURRENT_FORMAT_VERSION,
+        data: &note,
+    };
+    write_atomically(path, to_json(&envelope)?)?;
+
+    Ok(MigratedNote {
+        uuid: note.uuid,
+        file_path: path.to_path_buf(),
+        from_version,
+    })
+}
+
+#[tauri::command]
+pub fn get_migration_report(data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.migration_report)
+}
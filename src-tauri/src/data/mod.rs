@@ -1,18 +1,72 @@
-use crate::core::utils::fs::write_atomically;
+pub mod archive;
+pub mod backend;
+pub mod backups;
+pub mod bodies;
+pub mod error;
+pub mod events;
+pub mod graph;
+pub mod import;
+pub mod links;
+pub mod locked;
+pub mod markdown;
+pub mod migrations;
+pub mod note;
+pub mod notebooks;
+pub mod render;
+pub mod revisions;
+pub mod search;
+pub mod settings;
+pub mod site;
+pub mod summary;
+pub mod tags;
+pub mod trash;
+pub mod vault;
+pub mod watcher;
+
+use crate::core::utils::fs::{write_atomically, write_string_atomically};
 use crate::core::utils::json::to_json;
+use crate::core::utils::time::now_millis;
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
-use std::fs::{create_dir, read_dir};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tauri::{Config, State};
 use uuid::Uuid;
 
+use self::archive::{read_archive, write_archive, ArchiveSummary, OnConflict};
+use self::backend::{note_uuid, Backend};
+use self::backups::{BackupInfo, BackupReason, Backups, RestoreReport, Snapshot};
+use self::bodies::{digest, BodyCache, BODY_CACHE_SIZE};
+use self::error::NoteError;
+use self::events::{EventBus, NoteEvent};
+use self::graph::{build_graph, Graph};
+use self::import::{enex, obsidian, ImportProgress, ImportReport};
+use self::links::{
+    file_name, rewrite_links, wiki_to_markdown, LinkIndex, LinkTarget, LinkedNote, OutgoingLink,
+};
+use self::locked::{placeholder, LockedNote, UnlockedNote};
+use self::migrations::{Envelope, MigrationReport, CURRENT_FORMAT_VERSION, MIGRATION_BACKUPS_DIR};
+use self::note::{Note, NOTE_SCHEMA_VERSION};
+use self::notebooks::{Notebook, NotebookTree, Notebooks};
+use self::render::Renderer;
+use self::revisions::Revisions;
+use self::search::{SearchHit, SearchIndex};
+use self::settings::{Settings, StorageFormat};
+use self::site::{write_site, SiteReport};
+use self::summary::{NotePage, SortBy, SummaryIndex};
+use self::tags::{normalize_tag, normalize_tags, TagCount, TagExpr, TagIndex};
+use self::trash::{Trash, TrashEntry};
+use self::vault::{Sealed, Vault, VaultStatus};
+use self::watcher::NotesChanged;
+
 pub struct AppData {
     pub app_dir: PathBuf,
     pub data_dir: PathBuf,
+    pub trash_dir: PathBuf,
+    pub revisions_dir: PathBuf,
 }
 
 impl AppData {
@@ -22,157 +76,692 @@ impl AppData {
         AppData {
             app_dir: app_dir.clone(),
             data_dir: app_dir.join("data"),
+            trash_dir: app_dir.join("trash"),
+            revisions_dir: app_dir.join("revisions"),
         }
     }
 }
 
+// Whether a file in the data directory holds a note. Hidden files are app
+// bookkeeping (or sync client junk) and are skipped
+pub fn is_note_path(path: &PathBuf) -> bool {
+    let is_hidden = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name.starts_with('.'),
+        None => true,
+    };
+    let is_note = matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("json") | Some("md")
+    );
+    !is_hidden && is_note && path.is_file()
+}
+
 pub enum InsertKind {
     Uuid(Uuid),
     String(String),
 }
 
 // #[serde(rename_all = "camelCase")]
-#[derive(Serialize, Debug, Deserialize, Clone)]
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
 pub struct NoteFile {
     pub file_path: PathBuf,
     pub uuid: Option<Uuid>,
-    pub content: String,
+    pub content: Note,
+    // The encrypted note when stored in a vault, with `content` left empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault: Option<Sealed>,
+    // Set when the note is locked with its own password, with `content` holding a placeholder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked: Option<LockedNote>,
 }
 
 impl NoteFile {
-    pub fn new(file_path: &PathBuf, content: &String) -> Self {
+    pub fn new(file_path: &PathBuf, content: &Note) -> Self {
         let uuid = Uuid::new_v4();
         Self {
             file_path: file_path
                 .join(format!("{}.json", uuid.clone().to_string()))
                 .to_path_buf(),
-            content: content.to_string(),
+            content: content.to_owned(),
             uuid: Some(uuid),
+            vault: None,
+            locked: None,
         }
     }
-    // Load the note file from disk (currently unused)
+    // Load the note file from disk, upgrading older formats in memory.
+    // Markdown files without a uuid in their front matter get one written back
     pub fn load(path: &PathBuf) -> Result<Self, String> {
-        let note = match std::fs::read_to_string(path) {
-            Ok(note_str) => {
-                let note_file: NoteFile = match serde_json::from_str(&note_str) {
-                    Ok(note) => note,
-                    Err(e) => throw!("Could not parse reminders file: {}", e),
-                };
-                note_file
+        if markdown::is_markdown(path) {
+            let mut note = markdown::load(path)?;
+            if note.uuid.is_none() {
+                note.uuid = Some(Uuid::new_v4());
+                write_string_atomically(path, &markdown::render(&note)?)?;
             }
-            Err(e) => match e.kind() {
-                _ => throw!("{}", e.to_string()),
+            return Ok(note);
+        }
+
+        let note_str = match std::fs::read_to_string(path) {
+            Ok(note_str) => note_str,
+            Err(e) => throw!("{}", e.to_string()),
+        };
+        let value: Value = match serde_json::from_str(&note_str) {
+            Ok(value) => value,
+            Err(e) => throw!("Could not parse note file: {}", e),
+        };
+        let (data, _) = migrations::upgrade(value, path)?;
+
+        match serde_json::from_value(data) {
+            Ok(note) => Ok(note),
+            Err(e) => throw!("Could not parse note file: {}", e),
+        }
+    }
+    // Load what's needed to list a note, with an empty body. Markdown files
+    // are only read up to the end of their front matter; JSON is parsed whole
+    pub fn load_header(path: &PathBuf) -> Result<Self, String> {
+        let mut note = match markdown::is_markdown(path) {
+            true => match markdown::load_header(path)? {
+                note if note.uuid.is_some() => note,
+                // Read in full to get a uuid written back
+                _ => Self::load(path)?,
             },
+            false => Self::load(path)?,
         };
+        note.content.body = String::new();
         Ok(note)
     }
-    // Save the note file to disk and update self
-    pub fn save(&mut self, buf: &[u8]) -> Result<Self, String> {
-        self.content = String::from_utf8(buf.to_vec()).unwrap();
-        match write_atomically(&self.file_path.to_path_buf(), to_json(self).unwrap()) {
-            Ok(_) => {}
-            Err(e) => throw!("File save error: {}", e.to_string()),
+    // Save the note in the given format. The file is renamed when the format
+    // changes, or for markdown when the title no longer matches the file name
+    pub fn save_as(&mut self, content: &Note, format: StorageFormat) -> Result<Self, String> {
+        self.content = content.to_owned();
+        let previous_path = self.file_path.to_path_buf();
+        let dir = match previous_path.parent() {
+            Some(dir) => dir.to_path_buf(),
+            None => throw!("Note file {} has no folder", previous_path.display()),
+        };
+        self.file_path = match format {
+            StorageFormat::Json if !markdown::is_markdown(&previous_path) => {
+                previous_path.to_path_buf()
+            }
+            StorageFormat::Json => dir.join(format!("{}.json", self.uuid.unwrap_or_default())),
+            StorageFormat::Markdown => {
+                markdown::note_path(&dir, &self.content.title, Some(&previous_path))
+            }
+        };
+
+        let result = match format {
+            StorageFormat::Json => {
+                let envelope = Envelope {
+                    format_version: CURRENT_FORMAT_VERSION,
+                    data: &*self,
+                };
+                write_atomically(&self.file_path, to_json(&envelope)?)
+            }
+            StorageFormat::Markdown => {
+                write_string_atomically(&self.file_path, &markdown::render(self)?)
+            }
+        };
+        if let Err(e) = result {
+            self.file_path = previous_path;
+            throw!("File save error: {}", e.to_string());
         }
 
+        if self.file_path != previous_path && previous_path.exists() {
+            if let Err(e) = std::fs::remove_file(&previous_path) {
+                eprintln!("Error removing {}: {}", previous_path.display(), e);
+            }
+        }
         Ok(self.to_owned())
     }
 }
 
-impl Default for NoteFile {
-    fn default() -> Self {
-        Self {
-            file_path: Default::default(),
-            uuid: Default::default(),
-            content: Default::default(),
-        }
+// Which notes an export includes. An empty filter includes every note
+#[derive(Debug, Clone, Default)]
+pub struct NoteFilter {
+    // A tag filter expression, as accepted by `get_files`
+    pub tags: Option<TagExpr>,
+    // Only notes in this notebook or the notebooks nested in it
+    pub notebook: Option<Uuid>,
+}
+
+impl NoteFilter {
+    pub fn new(tags: Option<&str>, notebook: Option<Uuid>) -> Result<Self, String> {
+        let tags = match tags {
+            Some(tags) if !tags.trim().is_empty() => Some(TagExpr::parse(tags)?),
+            _ => None,
+        };
+        Ok(Self { tags, notebook })
+    }
+
+    pub fn matches(&self, note: &Note, notebooks: &Notebooks) -> bool {
+        let in_tags = match &self.tags {
+            Some(expr) => expr.matches(&note.tags),
+            None => true,
+        };
+        let in_notebook = match &self.notebook {
+            Some(notebook) => notebooks.contains(notebook, note.parent),
+            None => true,
+        };
+        in_tags && in_notebook
     }
 }
 
 pub trait KV {
-    fn set(&mut self, uuid: InsertKind, content: &String);
-
-    fn get(&self, uuid: Option<Uuid>) -> Option<NoteFile>;
     fn get_all(&self) -> Vec<NoteFile>;
 
     fn has_key(&self, uuid: &Option<Uuid>) -> bool;
 }
 
-#[derive(Serialize, Debug, Deserialize, Clone)]
-pub struct Notes {
+// Every note, kept in memory and written through to a storage backend.
+// Only the headers stay in `entries`; bodies are read on demand through `bodies`
+#[derive(Debug)]
+pub struct Notes<B: Backend = Box<dyn Backend>> {
     pub data_path: PathBuf,
     pub entries: HashMap<Uuid, NoteFile>,
+    pub backend: B,
+    pub search: SearchIndex,
+    pub revisions: Revisions,
+    pub tags: TagIndex,
+    pub links: LinkIndex,
+    pub summaries: SummaryIndex,
+    pub events: EventBus,
+    bodies: Mutex<BodyCache>,
+    // Locked notes decrypted for this session
+    unlocked: HashMap<Uuid, UnlockedNote>,
 }
 
-impl Notes {
-    // Initialize Notes without reading the data directory
-    pub fn new(data_path: &PathBuf) -> Notes {
-        Self {
-            entries: HashMap::<Uuid, NoteFile>::new(),
+impl<B: Backend> Notes<B> {
+    // Initialize Notes with everything stored in the backend. Bodies are only
+    // read for notes changed since `summaries` was persisted
+    pub fn new(data_path: &PathBuf, backend: B, summaries: SummaryIndex) -> Self {
+        let mut notes = Self {
+            entries: HashMap::new(),
             data_path: data_path.to_path_buf(),
-        }
+            backend,
+            search: SearchIndex::default(),
+            revisions: Revisions::default(),
+            tags: TagIndex::default(),
+            links: LinkIndex::default(),
+            summaries,
+            events: EventBus::default(),
+            bodies: Mutex::new(BodyCache::new(BODY_CACHE_SIZE)),
+            unlocked: HashMap::new(),
+        };
+        notes.reload();
+        notes
     }
-    // Initialize Notes from the data directory
-    pub fn new_from_data_dir(data_path: &PathBuf) -> Notes {
+    // Read every note's header from the backend again, e.g. once the vault is unlocked
+    pub fn reload(&mut self) {
         let mut entries = HashMap::new();
-        for entry in read_dir(data_path).unwrap() {
-            if let Ok(e) = entry {
-                if let Ok(note) = NoteFile::load(&e.path()) {
-                    entries.insert(note.uuid.unwrap().to_owned(), note);
+        match self.backend.list_headers() {
+            Ok(notes) => {
+                for note in notes {
+                    if let Some(uuid) = note.uuid {
+                        entries.insert(uuid, note);
+                    }
                 }
-                {
-                    eprintln!("ERROR! LOAD FROM DIR!!!");
+            }
+            Err(e) => eprintln!("Error loading notes: {}", e),
+        }
+        // Bodies read to catch the summaries up are cached for the search index
+        let bodies = self.bodies.get_mut().unwrap();
+        bodies.clear();
+        let bodies = Mutex::new(std::mem::take(bodies));
+        let backend = &self.backend;
+        self.summaries
+            .reconcile(&entries, &|uuid| match backend.load(uuid) {
+                Ok(note) => {
+                    if let Some(note) = &note {
+                        bodies.lock().unwrap().insert(uuid, &note.content.body);
+                    }
+                    note
                 }
-            };
+                Err(e) => {
+                    eprintln!("Error loading note {}: {}", uuid, e);
+                    None
+                }
+            });
+        self.bodies = bodies;
+        let summaries = &self.summaries;
+        self.tags = TagIndex::new_from_entries(&entries);
+        self.links = LinkIndex::new_from_entries(&entries, &|uuid| match summaries.facts(uuid) {
+            Some(facts) => facts.links.to_owned(),
+            None => vec![],
+        });
+        let bodies = self.bodies.get_mut().unwrap();
+        for uuid in entries.keys() {
+            if let (Some(facts), None) = (self.summaries.facts(uuid), bodies.digest(uuid)) {
+                bodies.remember(uuid, facts.digest);
+            }
         }
-        Self {
-            entries: entries,
-            data_path: data_path.to_path_buf(),
+        self.entries = entries;
+    }
+    // Drop every note from memory, e.g. when the vault is locked
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.unlocked.clear();
+        self.tags = TagIndex::default();
+        self.links = LinkIndex::default();
+        self.summaries = SummaryIndex::new(&self.summaries.index_path);
+        self.search = SearchIndex::default();
+        self.bodies.get_mut().unwrap().clear();
+    }
+    // Use a persisted search index, catching it up with any notes changed since it was saved
+    pub fn attach_search_index(&mut self, mut index: SearchIndex) {
+        index.reconcile(&self.entries, &|uuid| match self.full(uuid) {
+            Ok(note) => Some(note),
+            Err(e) => {
+                eprintln!("Error indexing note {}: {}", uuid, e);
+                None
+            }
+        });
+        self.search = index;
+    }
+    // Hold on to a note's header, with its body going to the cache
+    fn keep(&mut self, uuid: Uuid, mut note: NoteFile) {
+        let body = std::mem::take(&mut note.content.body);
+        self.bodies.get_mut().unwrap().insert(&uuid, &body);
+        self.entries.insert(uuid, note);
+    }
+    // A note's body, read back from the backend when it isn't cached
+    fn body(&self, uuid: &Uuid) -> Result<String, NoteError> {
+        let mut bodies = self.bodies.lock().unwrap();
+        if let Some(body) = bodies.get(uuid) {
+            return Ok(body);
+        }
+        match self.backend.load(uuid)? {
+            Some(note) => {
+                bodies.insert(uuid, &note.content.body);
+                Ok(note.content.body)
+            }
+            None => Err(NoteError::not_found(uuid)),
+        }
+    }
+    // A note as stored, with its body. Locked notes keep their placeholder
+    pub fn full(&self, uuid: &Uuid) -> Result<NoteFile, NoteError> {
+        let mut note = match self.entries.get(uuid) {
+            Some(entry) => entry.to_owned(),
+            None => return Err(NoteError::not_found(uuid)),
+        };
+        note.content.body = self.body(uuid)?;
+        Ok(note)
+    }
+    // Stamp the version and modification time on an edited note
+    fn stamp(content: &Note) -> Note {
+        let mut note = content.to_owned();
+        note.version = NOTE_SCHEMA_VERSION;
+        note.modified_at = now_millis();
+        note.tags = normalize_tags(&note.tags);
+        note
+    }
+    fn update_indexes(&mut self, uuid: &Uuid, note: &NoteFile) {
+        // Backends with their own full-text index keep it up to date on save
+        if !self.backend.full_text() {
+            self.search.update(uuid, note);
         }
+        self.tags.update(uuid, &note.content.tags);
+        self.summaries.update(uuid, note);
+        let targets = match self.summaries.facts(uuid) {
+            Some(facts) => facts.links.to_owned(),
+            None => vec![],
+        };
+        self.links.update(uuid, note, targets);
     }
-    // Insert or update a note into the HashMap
-    pub fn insert(&mut self, key: InsertKind, content: &str) {
+    // The readable content of a note, which for locked notes is only there once unlocked
+    pub fn content(&self, uuid: &Uuid) -> Result<Note, NoteError> {
+        let entry = match self.entries.get(uuid) {
+            Some(entry) => entry,
+            None => return Err(NoteError::not_found(uuid)),
+        };
+        match (&entry.locked, self.unlocked.get(uuid)) {
+            (None, _) => Ok(self.full(uuid)?.content),
+            (Some(_), Some(unlocked)) => Ok(unlocked.note.to_owned()),
+            (Some(_), None) => Err(format!("Note {} is locked", uuid).into()),
+        }
+    }
+    // Insert or update a note into the HashMap, stamping its timestamps
+    pub fn insert(&mut self, key: InsertKind, content: &Note) -> Result<Uuid, String> {
         match key {
             InsertKind::Uuid(uuid) => {
-                if self.entries.contains_key(&uuid) {
-                    let entry = self.entries.entry(uuid).or_default();
-                    *entry = entry.save(&content.as_bytes()).unwrap()
-                };
+                self.update_many(vec![(uuid, content.to_owned())])?;
+                Ok(uuid)
             }
-            InsertKind::String(_title) => {
-                let content_bytes = &content.as_bytes();
-                let mut new_note =
-                    NoteFile::new(&self.data_path.to_path_buf(), &content.to_string());
-
-                new_note
-                    .save(content_bytes)
-                    .expect("Error saving newly inserted note");
-
-                self.entries
-                    .insert(new_note.uuid.unwrap().to_owned(), new_note);
+            InsertKind::String(title) => {
+                let mut note = content.to_owned();
+                note.title = title;
+                self.create(&note)
             }
         }
     }
-}
+    // Add a new note, returning its uuid
+    pub fn create(&mut self, content: &Note) -> Result<Uuid, String> {
+        let mut note = Self::stamp(content);
+        note.created_at = note.modified_at;
+        let new_note = self
+            .backend
+            .save(&NoteFile::new(&self.data_path.to_path_buf(), &note))?;
+        let uuid = note_uuid(&new_note)?;
 
-impl KV for Notes {
-    fn set(&mut self, uuid: InsertKind, content: &String) {
-        match uuid {
-            InsertKind::Uuid(uuid) => {
-                self.entries
-                    .insert(uuid, NoteFile::new(&self.data_path.to_path_buf(), content));
+        self.update_indexes(&uuid, &new_note);
+        if let Err(e) = self.revisions.record(&uuid, None, &note) {
+            eprintln!("Error recording revision: {}", e);
+        }
+        self.events.emit(NoteEvent::Created(new_note.to_owned()));
+        self.keep(uuid, new_note);
+        Ok(uuid)
+    }
+    // Add several new notes as they are, keeping their timestamps, e.g. from
+    // an import. They are saved to the backend in one go
+    pub fn create_many(&mut self, contents: &[Note]) -> Result<Vec<Uuid>, String> {
+        let new_notes: Vec<NoteFile> = contents
+            .iter()
+            .map(|content| {
+                let mut note = content.to_owned();
+                note.version = NOTE_SCHEMA_VERSION;
+                note.tags = normalize_tags(&note.tags);
+                NoteFile::new(&self.data_path, &note)
+            })
+            .collect();
+        self.put_many(&new_notes)
+    }
+    // Save notes as they are, keeping their uuids and timestamps, e.g. when
+    // restoring an archive. Notes that already exist are replaced
+    pub fn put_many(&mut self, notes: &[NoteFile]) -> Result<Vec<Uuid>, String> {
+        // Read before saving, since bodies that aren't cached come from the backend
+        let mut previous: HashMap<Uuid, Note> = notes
+            .iter()
+            .filter_map(|note| note.uuid)
+            .filter_map(|uuid| Some((uuid, self.content(&uuid).ok()?)))
+            .collect();
+        let mut uuids = vec![];
+        for note in self.backend.save_many(notes)? {
+            let uuid = note_uuid(&note)?;
+            let previous = previous.remove(&uuid);
+            self.update_indexes(&uuid, &note);
+            if note.locked.is_none() {
+                if let Err(e) = self
+                    .revisions
+                    .record(&uuid, previous.as_ref(), &note.content)
+                {
+                    eprintln!("Error recording revision: {}", e);
+                }
             }
+            self.unlocked.remove(&uuid);
+            self.events.emit(match self.entries.contains_key(&uuid) {
+                true => NoteEvent::Updated(note.to_owned()),
+                false => NoteEvent::Created(note.to_owned()),
+            });
+            self.keep(uuid, note);
+            uuids.push(uuid);
+        }
+        Ok(uuids)
+    }
+    // Update several existing notes, saving them to the backend in one go so
+    // that either all of them are written or none are. Unknown uuids are skipped
+    pub fn update_many(&mut self, updates: Vec<(Uuid, Note)>) -> Result<(), String> {
+        let mut previous = vec![];
+        let mut changed = vec![];
+        let mut unlocked = vec![];
+        let mut names = HashMap::new();
+        for (uuid, content) in updates {
+            // Fall back to the backend for notes not held in memory
+            let existing = match self.entries.contains_key(&uuid) {
+                true => Some(self.full(&uuid)?),
+                false => self.backend.load(&uuid)?,
+            };
+            let mut entry = match existing {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let mut note = Self::stamp(&content);
+            if entry.locked.is_some() {
+                // Locked notes are saved encrypted and keep no history
+                let session = match self.unlocked.get(&uuid) {
+                    Some(session) => session,
+                    None => throw!("Note {} is locked", uuid),
+                };
+                note.created_at = session.note.created_at;
+                entry.locked = Some(session.seal(&note)?);
+                entry.content = placeholder(&note);
+                previous.push(None);
+                unlocked.push((uuid, note));
+            } else {
+                names.insert(uuid, (entry.content.title.to_owned(), file_name(&entry)));
+                note.created_at = entry.content.created_at;
+                previous.push(Some(std::mem::replace(&mut entry.content, note)));
+            }
+            changed.push(entry);
+        }
 
-            InsertKind::String(_string) => {}
+        let saved = self.backend.save_many(&changed)?;
+        let mut renamed = vec![];
+        for (entry, previous) in saved.into_iter().zip(previous) {
+            let uuid = note_uuid(&entry)?;
+            if let Some((title, file)) = names.remove(&uuid) {
+                if title.trim() != entry.content.title.trim() && !title.trim().is_empty() {
+                    renamed.push((LinkTarget::Title(title), entry.content.title.to_owned()));
+                }
+                if let (Some(from), Some(to)) = (file, file_name(&entry)) {
+                    if from != to {
+                        renamed.push((LinkTarget::File(from), to));
+                    }
+                }
+            }
+            self.update_indexes(&uuid, &entry);
+            if let Some(previous) = previous {
+                if let Err(e) = self
+                    .revisions
+                    .record(&uuid, Some(&previous), &entry.content)
+                {
+                    eprintln!("Error recording revision: {}", e);
+                }
+            }
+            self.events.emit(NoteEvent::Updated(entry.to_owned()));
+            self.keep(uuid, entry);
         }
+        for (uuid, note) in unlocked {
+            if let Some(session) = self.unlocked.get_mut(&uuid) {
+                session.note = note;
+            }
+        }
+        self.follow_renames(renamed)
+    }
+    // Point links at renamed notes to their new names, unless another note
+    // still goes by the old one
+    fn follow_renames(&mut self, renamed: Vec<(LinkTarget, String)>) -> Result<(), String> {
+        let mut updates: HashMap<Uuid, Note> = HashMap::new();
+        for (from, to) in renamed {
+            if self.links.resolve(&from).is_some() {
+                continue;
+            }
+            for uuid in self.links.linking_to(&from) {
+                let mut note = match updates.get(&uuid) {
+                    Some(note) => note.to_owned(),
+                    // Locked notes can't be rewritten without their password
+                    None => match self.content(&uuid) {
+                        Ok(note) => note,
+                        Err(_) => continue,
+                    },
+                };
+                if let Some(body) = rewrite_links(&note.body, &from, &to) {
+                    note.body = body;
+                    updates.insert(uuid, note);
+                }
+            }
+        }
+        match updates.is_empty() {
+            true => Ok(()),
+            false => self.update_many(updates.into_iter().collect()),
+        }
+    }
+    // Save a note as it is, without touching its timestamps, e.g. when it comes back from the trash
+    pub fn restore(&mut self, note: NoteFile) -> Result<(), String> {
+        let uuid = note_uuid(&note)?;
+        let note = self.backend.save(&note)?;
+        self.update_indexes(&uuid, &note);
+        self.events.emit(match self.entries.contains_key(&uuid) {
+            true => NoteEvent::Updated(note.to_owned()),
+            false => NoteEvent::Created(note.to_owned()),
+        });
+        self.keep(uuid, note);
+        Ok(())
+    }
+    // Remove a note from the backend and the HashMap
+    pub fn delete(&mut self, uuid: &Uuid) -> Result<Option<NoteFile>, String> {
+        self.backend.delete(uuid)?;
+        self.unlocked.remove(uuid);
+        self.search.remove(uuid);
+        self.tags.remove(uuid);
+        self.links.remove(uuid);
+        self.summaries.remove(uuid);
+        self.bodies.get_mut().unwrap().remove(uuid);
+        let removed = self.entries.remove(uuid);
+        if removed.is_some() {
+            self.events.emit(NoteEvent::Deleted { uuid: *uuid });
+        }
+        Ok(removed)
     }
+    // Encrypt a note with its own password. Its history is dropped, since
+    // revisions are stored in plaintext
+    pub fn lock(&mut self, uuid: &Uuid, password: &str) -> Result<(), NoteError> {
+        let note = self.content(uuid)?;
+        let (locked, session) = LockedNote::seal(&note, password)?;
+        let mut entry = self.entries[uuid].to_owned();
+        entry.locked = Some(locked);
+        entry.content = placeholder(&note);
 
-    fn get(&self, uuid: Option<Uuid>) -> std::option::Option<NoteFile> {
-        let result = self.entries.get(&uuid.unwrap()).unwrap();
-        Some(result.to_owned())
+        let entry = self.backend.save(&entry)?;
+        self.update_indexes(uuid, &entry);
+        self.events.emit(NoteEvent::Updated(entry.to_owned()));
+        self.keep(*uuid, entry);
+        self.unlocked.insert(*uuid, session);
+        Ok(self.revisions.remove(uuid)?)
+    }
+    // Decrypt a locked note for the rest of the session
+    pub fn unlock(&mut self, uuid: &Uuid, password: &str) -> Result<NoteFile, NoteError> {
+        let mut entry = match self.entries.get(uuid) {
+            Some(entry) => entry.to_owned(),
+            None => return Err(NoteError::not_found(uuid)),
+        };
+        let session = match &entry.locked {
+            Some(locked) => locked.open(password)?,
+            None => return Err(format!("Note {} is not locked", uuid).into()),
+        };
+        entry.content = session.note.to_owned();
+        self.unlocked.insert(*uuid, session);
+        Ok(entry)
     }
+    // Forget the decrypted content of a locked note
+    pub fn forget(&mut self, uuid: &Uuid) -> Result<(), NoteError> {
+        match self.entries.get(uuid) {
+            Some(entry) if entry.locked.is_some() => {
+                self.unlocked.remove(uuid);
+                Ok(())
+            }
+            Some(_) => Err(format!("Note {} is not locked", uuid).into()),
+            None => Err(NoteError::not_found(uuid)),
+        }
+    }
+    // Pick up note files changed outside the app. Files that match what is
+    // in memory, like the ones the app wrote itself, are left alone
+    pub fn reload_paths(&mut self, paths: &[PathBuf]) -> NotesChanged {
+        let mut changes = NotesChanged::default();
+        // Files renamed by a sync client show up as a new path and a gone one
+        let (present, gone): (Vec<&PathBuf>, Vec<&PathBuf>) =
+            paths.iter().partition(|path| path.exists());
+        for path in present {
+            let note = match self.backend.load_path(path) {
+                Ok(Some(note)) => note,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("Error loading {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let uuid = match note.uuid {
+                Some(uuid) => uuid,
+                None => continue,
+            };
+            let mut header = note.content.to_owned();
+            let is_known_body = {
+                let bodies = self.bodies.get_mut().unwrap();
+                bodies.digest(&uuid) == Some(digest(&std::mem::take(&mut header.body)))
+            };
+            let previous = match self.entries.get(&uuid) {
+                Some(entry)
+                    if entry.content == header && entry.locked == note.locked && is_known_body =>
+                {
+                    continue
+                }
+                // The old body is gone from disk, so it is only known if still cached
+                Some(entry) if entry.locked.is_none() => {
+                    let cached = self.bodies.get_mut().unwrap().get(&uuid);
+                    cached.map(|body| Note {
+                        body,
+                        ..entry.content.to_owned()
+                    })
+                }
+                _ => None,
+            };
+            self.update_indexes(&uuid, &note);
+            if note.locked.is_none() {
+                if let Err(e) = self
+                    .revisions
+                    .record(&uuid, previous.as_ref(), &note.content)
+                {
+                    eprintln!("Error recording revision: {}", e);
+                }
+            }
+            self.unlocked.remove(&uuid);
+            self.events.emit(match self.entries.contains_key(&uuid) {
+                true => NoteEvent::Updated(note.to_owned()),
+                false => NoteEvent::Created(note.to_owned()),
+            });
+            self.keep(uuid, note);
+            changes.changed.push(uuid);
+        }
+        for path in gone {
+            let uuid = match self.backend.forget_path(path) {
+                Some(uuid) => uuid,
+                None => continue,
+            };
+            if self.entries.remove(&uuid).is_some() {
+                self.unlocked.remove(&uuid);
+                self.search.remove(&uuid);
+                self.tags.remove(&uuid);
+                self.links.remove(&uuid);
+                self.summaries.remove(&uuid);
+                self.bodies.get_mut().unwrap().remove(&uuid);
+                self.events.emit(NoteEvent::Deleted { uuid });
+                changes.removed.push(uuid);
+            }
+        }
+        changes
+    }
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, String> {
+        match self.backend.full_text() {
+            true => self.backend.search(query, limit),
+            false => {
+                Ok(self
+                    .search
+                    .search(query, &self.entries, &|uuid| self.body(uuid).ok(), limit))
+            }
+        }
+    }
+}
 
+impl<B: Backend> KV for Notes<B> {
     fn get_all(&self) -> Vec<NoteFile> {
-        let result = self.entries.values().map(|e| e.to_owned()).collect();
+        let result = self
+            .entries
+            .keys()
+            .filter_map(|uuid| match self.full(uuid) {
+                Ok(note) => Some(note),
+                Err(e) => {
+                    eprintln!("Error loading note {}: {}", uuid, e);
+                    None
+                }
+            })
+            .collect();
         result
     }
 
@@ -186,41 +775,126 @@ impl KV for Notes {
 }
 
 #[derive(Debug)]
-pub struct Store {
+pub struct Store<B: Backend = Box<dyn Backend>> {
     pub data_path: PathBuf,
-    notes: Arc<Mutex<Notes>>,
+    notes: Arc<Mutex<Notes<B>>>,
+    trash: Arc<Mutex<Trash>>,
+    vault: Arc<Mutex<Vault>>,
+    notebooks: Arc<Mutex<Notebooks>>,
+    renderer: Renderer,
+    backups: Backups,
+    events: EventBus,
+    pub revisions: Revisions,
+    pub migration_report: MigrationReport,
+    migration_backups: PathBuf,
 }
 
-impl Store {
-    pub fn new(data_path: AppData) -> Store {
-        if data_path.data_dir.is_dir() {
-            Self {
-                data_path: data_path.data_dir.clone(),
-                notes: Arc::new(Mutex::new(Notes::new_from_data_dir(&data_path.data_dir))),
+impl<B: Backend> Store<B> {
+    pub fn new(data_path: AppData, settings: &Settings, vault: Vault, mut backend: B) -> Self {
+        let max_age = Duration::from_secs(settings.trash_retention_days * 24 * 60 * 60);
+        let mut trash = Trash::new(&data_path.trash_dir, max_age);
+        let revisions = Revisions::new(&data_path.revisions_dir, vault.key());
+        match trash.purge_expired() {
+            Ok(purged) => {
+                for uuid in purged {
+                    if let Err(e) = revisions.remove(&uuid) {
+                        eprintln!("{}", e);
+                    }
+                }
             }
-        } else {
-            create_dir(data_path.data_dir.clone()).unwrap();
-            Self {
-                data_path: data_path.data_dir.clone(),
-                notes: Arc::new(Mutex::new(Notes::new(&data_path.data_dir))),
+            Err(e) => eprintln!("Error purging trash: {}", e),
+        }
+
+        let migration_backups = data_path.app_dir.join(MIGRATION_BACKUPS_DIR);
+        let migration_report = match backend.migrate(&migration_backups) {
+            Ok(report) => report,
+            // Nothing was rewritten; notes are still upgraded in memory when loaded
+            Err(e) => {
+                eprintln!("Skipping migration, could not back up notes: {}", e);
+                MigrationReport::default()
             }
+        };
+        for note in migration_report.migrated.iter() {
+            eprintln!(
+                "Migrated {} from format {} to {}",
+                note.file_path.display(),
+                note.from_version,
+                migration_report.to_version
+            );
+        }
+        for failed in migration_report.failed.iter() {
+            eprintln!(
+                "Could not migrate {}: {}",
+                failed.file_path.display(),
+                failed.error
+            );
+        }
+
+        // Persisted indexes would give away the words in encrypted notes,
+        // so the vault rebuilds them in memory on every unlock
+        let summaries = match vault.key().is_enabled() {
+            true => SummaryIndex::default(),
+            false => SummaryIndex::load(&data_path.app_dir.join("summary-index.json")),
+        };
+        let mut notes = Notes::new(&data_path.data_dir, backend, summaries);
+        if !notes.backend.full_text() && !vault.key().is_enabled() {
+            notes.attach_search_index(SearchIndex::load(
+                &data_path.app_dir.join("search-index.json"),
+            ));
+        }
+        notes.revisions = revisions.clone();
+        let events = EventBus::default();
+        notes.events = events.clone();
+        let notebooks = Notebooks::new(&data_path.data_dir, vault.key());
+
+        Self {
+            data_path: data_path.data_dir.clone(),
+            notes: Arc::new(Mutex::new(notes)),
+            trash: Arc::new(Mutex::new(trash)),
+            vault: Arc::new(Mutex::new(vault)),
+            notebooks: Arc::new(Mutex::new(notebooks)),
+            renderer: Renderer::default(),
+            backups: Backups::new(&data_path.app_dir, settings),
+            events,
+            revisions,
+            migration_report,
+            migration_backups,
         }
     }
 
-    pub fn set(&self, key: InsertKind, content: String) {
+    pub fn set(&self, key: InsertKind, content: Note) -> Result<Uuid, String> {
         let mut data = self.notes.lock().unwrap();
-        data.insert(key, &content.clone());
+        data.insert(key, &content)
     }
 
-    pub fn set_new(&self, key: String, content: String) {
+    pub fn set_new(&self, key: String, content: Note) -> Result<Uuid, String> {
         let mut data = self.notes.lock().unwrap();
-        data.insert(InsertKind::String(key), &content);
+        data.insert(InsertKind::String(key), &content)
+    }
+
+    // A single note with its body, read from the backend if it isn't cached
+    pub fn get(&self, key: &Uuid) -> Result<NoteFile, NoteError> {
+        let mut notes = self.get_many(&[*key])?;
+        Ok(notes.remove(0))
     }
 
-    pub fn get(&self, key: Option<Uuid>) -> NoteFile {
+    // Several notes in the order asked for. If any of them doesn't exist,
+    // the error lists all that are missing
+    pub fn get_many(&self, keys: &[Uuid]) -> Result<Vec<NoteFile>, NoteError> {
         let data = self.notes.lock().unwrap();
-        let note = &data.get(key.to_owned()).unwrap();
-        note.to_owned()
+        let missing: Vec<Uuid> = keys
+            .iter()
+            .filter(|key| !data.entries.contains_key(key))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            return Err(NoteError::NotFound { uuids: missing });
+        }
+        let notes = keys
+            .iter()
+            .map(|key| data.full(key))
+            .collect::<Result<Vec<NoteFile>, NoteError>>()?;
+        Ok(notes)
     }
 
     pub fn has_key(&self, key: Option<Uuid>) -> bool {
@@ -232,39 +906,675 @@ impl Store {
         key_exists
     }
 
-    pub fn get_all(&self) -> Vec<NoteFile> {
-        let lock = self.notes.lock();
-        let data = lock.unwrap();
+    // Every note with an empty body, for listing them without reading the
+    // bodies from the backend. `get` gives the whole note
+    pub fn get_headers(&self) -> Vec<NoteFile> {
+        let data = self.notes.lock().unwrap();
+        data.entries.values().cloned().collect()
+    }
 
-        let result: Vec<NoteFile> = data.get_all();
-        result
+    // Move a note into the trash, returning it as it was
+    pub fn delete(&self, key: &Uuid) -> Result<NoteFile, NoteError> {
+        let mut data = self.notes.lock().unwrap();
+        let mut trash = self.trash.lock().unwrap();
+
+        let note = data.full(key)?;
+        trash.put(&data.backend.seal(&note)?)?;
+        if let Err(e) = data.delete(key) {
+            trash.remove(&[*key])?;
+            return Err(e.into());
+        }
+        for uuid in trash.purge_expired()? {
+            self.revisions.remove(&uuid)?;
+        }
+        Ok(note)
+    }
+
+    // Move a note out of the trash and back into the store
+    pub fn restore(&self, key: &Uuid) -> Result<(), String> {
+        let mut data = self.notes.lock().unwrap();
+        let mut trash = self.trash.lock().unwrap();
+
+        let note = data.backend.unseal(trash.get(key)?)?;
+        data.restore(note)?;
+        trash.remove(&[*key])?;
+        Ok(())
+    }
+
+    pub fn empty_trash(&self) -> Result<(), String> {
+        let mut trash = self.trash.lock().unwrap();
+        for uuid in trash.empty()? {
+            self.revisions.remove(&uuid)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_trash(&self) -> Vec<TrashEntry> {
+        let trash = self.trash.lock().unwrap();
+        trash.list()
+    }
+
+    // Overwrite a note with an earlier revision, which is itself saved as a new revision
+    pub fn restore_revision(&self, key: &Uuid, id: u32) -> Result<(), NoteError> {
+        let mut data = self.notes.lock().unwrap();
+        let mut note = data.content(key)?;
+        let revision = self.revisions.load(key)?.get(id)?;
+        // The version being replaced stays in the history
+        self.revisions.keep(key)?;
+
+        note.title = revision.title;
+        note.body = revision.body;
+        data.insert(InsertKind::Uuid(key.to_owned()), &note)?;
+        Ok(())
+    }
+
+    // A page of note summaries for the list view
+    pub fn list_notes(
+        &self,
+        sort: SortBy,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<NotePage, String> {
+        let data = self.notes.lock().unwrap();
+        data.summaries.page(sort, cursor, limit)
+    }
+
+    // All notes whose tags match a filter expression like `work AND NOT done`,
+    // with empty bodies like `get_headers`
+    pub fn get_filtered(&self, filter: &str) -> Result<Vec<NoteFile>, String> {
+        let expr = TagExpr::parse(filter)?;
+        let data = self.notes.lock().unwrap();
+
+        let result = data
+            .entries
+            .values()
+            .filter(|note| expr.matches(&note.content.tags))
+            .cloned()
+            .collect();
+        Ok(result)
+    }
+
+    pub fn add_tag(&self, key: &Uuid, tag: &str) -> Result<(), NoteError> {
+        let mut data = self.notes.lock().unwrap();
+        let mut note = data.content(key)?;
+        note.tags.push(tag.to_string());
+        data.insert(InsertKind::Uuid(key.to_owned()), &note)?;
+        Ok(())
+    }
+
+    pub fn remove_tag(&self, key: &Uuid, tag: &str) -> Result<(), NoteError> {
+        let mut data = self.notes.lock().unwrap();
+        let mut note = data.content(key)?;
+        let tag = normalize_tag(tag);
+        note.tags.retain(|t| Some(t) != tag.as_ref());
+        data.insert(InsertKind::Uuid(key.to_owned()), &note)?;
+        Ok(())
+    }
+
+    // Rename a tag on every note carrying it, merging it into `to` if that tag already exists
+    pub fn rename_tag(&self, from: &str, to: &str) -> Result<(), String> {
+        let mut data = self.notes.lock().unwrap();
+        let from = match normalize_tag(from) {
+            Some(tag) => tag,
+            None => throw!("Tag name cannot be empty"),
+        };
+        let to = match normalize_tag(to) {
+            Some(tag) => tag,
+            None => throw!("Tag name cannot be empty"),
+        };
+
+        let mut updates = vec![];
+        for uuid in data.tags.notes_with(&from) {
+            let mut note = data.full(&uuid)?.content;
+            for tag in note.tags.iter_mut() {
+                if *tag == from {
+                    *tag = to.to_owned();
+                }
+            }
+            updates.push((uuid, note));
+        }
+        data.update_many(updates)
+    }
+
+    pub fn create_notebook(&self, name: &str, parent: Option<Uuid>) -> Result<Notebook, String> {
+        let mut notebooks = self.notebooks.lock().unwrap();
+        notebooks.create(name, parent)
+    }
+
+    pub fn rename_notebook(&self, id: &Uuid, name: &str) -> Result<(), String> {
+        let mut notebooks = self.notebooks.lock().unwrap();
+        notebooks.rename(id, name)
+    }
+
+    pub fn move_notebook(&self, id: &Uuid, parent: Option<Uuid>) -> Result<(), String> {
+        let mut notebooks = self.notebooks.lock().unwrap();
+        notebooks.reparent(id, parent)
+    }
+
+    // Delete a notebook. Its notes and child notebooks move up to its parent
+    pub fn delete_notebook(&self, id: &Uuid) -> Result<(), String> {
+        let mut data = self.notes.lock().unwrap();
+        let mut notebooks = self.notebooks.lock().unwrap();
+
+        let notebook = notebooks.remove(id)?;
+        let orphans: Vec<(Uuid, Note)> = data
+            .entries
+            .iter()
+            // Locked notes can't be rewritten without their password; they
+            // show up at the top level anyway once their notebook is gone
+            .filter(|(_, note)| note.content.parent == Some(*id) && note.locked.is_none())
+            .map(|(uuid, _)| {
+                let mut note = data.full(uuid)?.content;
+                note.parent = notebook.parent;
+                Ok((*uuid, note))
+            })
+            .collect::<Result<Vec<(Uuid, Note)>, String>>()?;
+        data.update_many(orphans)
+    }
+
+    pub fn move_note(&self, key: &Uuid, notebook: Option<Uuid>) -> Result<(), NoteError> {
+        let mut data = self.notes.lock().unwrap();
+        let notebooks = self.notebooks.lock().unwrap();
+
+        if let Some(notebook) = notebook {
+            notebooks.get(&notebook)?;
+        }
+        let mut note = data.content(key)?;
+        note.parent = notebook;
+        data.insert(InsertKind::Uuid(key.to_owned()), &note)?;
+        Ok(())
+    }
+
+    pub fn get_notebook_tree(&self) -> NotebookTree {
+        let data = self.notes.lock().unwrap();
+        let notebooks = self.notebooks.lock().unwrap();
+        notebooks.tree(&data.entries)
+    }
+
+    pub fn get_graph(&self, filter: &NoteFilter) -> Result<Graph, String> {
+        let data = self.notes.lock().unwrap();
+        let notebooks = self.notebooks.lock().unwrap();
+
+        if let Some(notebook) = filter.notebook {
+            notebooks.get(&notebook)?;
+        }
+        Ok(build_graph(&data.entries, &data.links, &notebooks, filter))
+    }
+
+    pub fn list_tags(&self) -> Vec<TagCount> {
+        let data = self.notes.lock().unwrap();
+        data.tags.counts()
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, String> {
+        let data = self.notes.lock().unwrap();
+        data.search(query, limit)
+    }
+
+    fn linked_note(data: &Notes<B>, uuid: &Uuid) -> Option<LinkedNote> {
+        data.entries.get(uuid).map(|note| LinkedNote {
+            uuid: *uuid,
+            title: note.content.title.to_owned(),
+        })
+    }
+
+    pub fn get_backlinks(&self, key: &Uuid) -> Result<Vec<LinkedNote>, NoteError> {
+        let data = self.notes.lock().unwrap();
+        if !data.entries.contains_key(key) {
+            return Err(NoteError::not_found(key));
+        }
+        let mut result: Vec<LinkedNote> = data
+            .links
+            .backlinks(key)
+            .iter()
+            .filter_map(|uuid| Self::linked_note(&data, uuid))
+            .collect();
+        result.sort_by_key(|n| n.title.to_lowercase());
+        Ok(result)
+    }
+
+    pub fn get_outgoing_links(&self, key: &Uuid) -> Result<Vec<OutgoingLink>, NoteError> {
+        let data = self.notes.lock().unwrap();
+        if !data.entries.contains_key(key) {
+            return Err(NoteError::not_found(key));
+        }
+        let result = data
+            .links
+            .outgoing(key)
+            .into_iter()
+            .map(|(target, uuid)| OutgoingLink {
+                target,
+                note: uuid.and_then(|uuid| Self::linked_note(&data, &uuid)),
+            })
+            .collect();
+        Ok(result)
+    }
+
+    // A note's body as sanitized HTML
+    pub fn render(&self, key: &Uuid) -> Result<String, NoteError> {
+        let data = self.notes.lock().unwrap();
+        let note = data.content(key)?;
+        Ok(self
+            .renderer
+            .render(&wiki_to_markdown(&note.body, &data.links)))
+    }
+
+    // Write the notes matching `filter` to `out_dir` as a static website.
+    // Locked notes are never exported
+    pub fn export_site(
+        &self,
+        filter: &NoteFilter,
+        out_dir: &PathBuf,
+        title: &str,
+    ) -> Result<SiteReport, String> {
+        if self.is_locked() {
+            throw!("The vault is locked");
+        }
+        let data = self.notes.lock().unwrap();
+        let notebooks = self.notebooks.lock().unwrap();
+
+        if let Some(notebook) = filter.notebook {
+            notebooks.get(&notebook)?;
+        }
+        let notes: Vec<(Uuid, Note)> = data
+            .entries
+            .iter()
+            .filter(|(_, note)| note.locked.is_none())
+            .filter(|(_, note)| filter.matches(&note.content, &notebooks))
+            .map(|(uuid, _)| Ok((*uuid, data.full(uuid)?.content)))
+            .collect::<Result<Vec<(Uuid, Note)>, String>>()?;
+        write_site(
+            &notes,
+            &data.links,
+            &self.renderer,
+            &data.data_path,
+            out_dir,
+            title,
+        )
+    }
+
+    pub fn export_archive(&self, path: &PathBuf) -> Result<ArchiveSummary, String> {
+        if self.is_locked() {
+            throw!("The vault is locked");
+        }
+        let data = self.notes.lock().unwrap();
+        let notebooks = self.notebooks.lock().unwrap();
+        write_archive(path, &data, &notebooks)
+    }
+
+    pub fn import_archive(
+        &self,
+        path: &PathBuf,
+        on_conflict: OnConflict,
+    ) -> Result<ImportReport, String> {
+        if self.is_locked() {
+            throw!("The vault is locked");
+        }
+        let mut data = self.notes.lock().unwrap();
+        let mut notebooks = self.notebooks.lock().unwrap();
+        read_archive(path, on_conflict, &mut data, &mut notebooks)
+    }
+
+    pub fn import_obsidian(&self, vault_dir: &PathBuf) -> Result<ImportReport, String> {
+        if self.is_locked() {
+            throw!("The vault is locked");
+        }
+        let mut data = self.notes.lock().unwrap();
+        let mut notebooks = self.notebooks.lock().unwrap();
+        obsidian::import_vault(vault_dir, &mut data, &mut notebooks)
+    }
+
+    pub fn import_enex(
+        &self,
+        path: &PathBuf,
+        progress: &mut dyn FnMut(&ImportProgress),
+    ) -> Result<ImportReport, String> {
+        if self.is_locked() {
+            throw!("The vault is locked");
+        }
+        let mut data = self.notes.lock().unwrap();
+        let mut notebooks = self.notebooks.lock().unwrap();
+        enex::import_file(path, &mut data, &mut notebooks, progress)
+    }
+
+    pub fn lock_note(&self, key: &Uuid, password: Option<&str>) -> Result<(), NoteError> {
+        let mut data = self.notes.lock().unwrap();
+        match password {
+            Some(password) => data.lock(key, password),
+            None => data.forget(key),
+        }
+    }
+
+    pub fn unlock_note(&self, key: &Uuid, password: &str) -> Result<NoteFile, NoteError> {
+        let mut data = self.notes.lock().unwrap();
+        data.unlock(key, password)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        let vault = self.vault.lock().unwrap();
+        vault.key().is_locked()
+    }
+
+    pub fn vault_status(&self) -> VaultStatus {
+        let vault = self.vault.lock().unwrap();
+        vault.status()
+    }
+
+    // Unlock the vault and load every note. The first unlock creates the
+    // vault and encrypts everything written before it existed, returning
+    // warnings about the plaintext copies it had to delete
+    pub fn unlock_vault(&self, passphrase: &str) -> Result<Vec<String>, String> {
+        let mut data = self.notes.lock().unwrap();
+        let mut trash = self.trash.lock().unwrap();
+        let mut vault = self.vault.lock().unwrap();
+        let mut notebooks = self.notebooks.lock().unwrap();
+
+        let created = vault.unlock(passphrase)?;
+        let mut warnings = vec![];
+        data.summaries = SummaryIndex::default();
+        data.reload();
+        *notebooks = Notebooks::new(&self.data_path, vault.key());
+        if created {
+            notebooks.save()?;
+            for note in data.get_all() {
+                data.restore(note)?;
+            }
+            for entry in trash.list() {
+                let note = trash.get(&entry.uuid)?;
+                trash.put(&data.backend.seal(&note)?)?;
+            }
+            self.revisions.rewrite_all()?;
+            data.backend.compact()?;
+            warnings = self.remove_plaintext_copies()?;
+        }
+        data.attach_search_index(SearchIndex::default());
+        Ok(warnings)
+    }
+
+    // Backups taken before the vault existed hold every note in plaintext
+    // and can't be sealed in place, so they go
+    fn remove_plaintext_copies(&self) -> Result<Vec<String>, String> {
+        let mut warnings = vec![];
+        let removed = self.backups.remove_all()?;
+        if !removed.is_empty() {
+            warnings.push(format!(
+                "Deleted {} backups taken before the vault, as they held notes unencrypted. New backups are encrypted",
+                removed.len()
+            ));
+        }
+        if self.migration_backups.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&self.migration_backups) {
+                throw!("Error removing {}: {}", self.migration_backups.display(), e);
+            }
+            warnings.push(
+                "Deleted the copies of your notes kept from before they were upgraded, as they were unencrypted"
+                    .to_string(),
+            );
+        }
+        Ok(warnings)
+    }
+
+    pub fn lock_vault(&self) -> Result<(), String> {
+        let mut data = self.notes.lock().unwrap();
+        let mut vault = self.vault.lock().unwrap();
+        let mut notebooks = self.notebooks.lock().unwrap();
+        vault.lock()?;
+        data.clear();
+        *notebooks = Notebooks::new(&self.data_path, vault.key());
+        Ok(())
+    }
+
+    pub fn change_passphrase(&self, old: &str, new: &str) -> Result<(), String> {
+        let mut vault = self.vault.lock().unwrap();
+        vault.change_passphrase(old, new)
+    }
+
+    pub fn backups_enabled(&self) -> bool {
+        self.backups.enabled()
+    }
+
+    pub fn backup_due(&self) -> bool {
+        self.backups.is_due()
+    }
+
+    // Call `listener` with every note created, updated or deleted from now on
+    pub fn subscribe(&self, listener: Box<dyn Fn(&NoteEvent) + Send>) {
+        self.events.subscribe(listener)
+    }
+
+    pub fn list_backups(&self) -> Vec<BackupInfo> {
+        self.backups.list()
+    }
+
+    pub fn backups(&self) -> &Backups {
+        &self.backups
+    }
+
+    // Back up the data directory and drop backups that fall outside the retention
+    pub fn backup(&self, reason: BackupReason) -> Result<BackupInfo, String> {
+        let backup = self.backups.write_snapshot(self.backup_snapshot(reason)?)?;
+        self.backups.prune()?;
+        Ok(backup)
+    }
+
+    // Copy the notes for a backup, to be zipped with `Backups::write_snapshot`
+    pub fn backup_snapshot(&self, reason: BackupReason) -> Result<Snapshot, String> {
+        let data = self.notes.lock().unwrap();
+        self.backups
+            .snapshot(&data.data_path, &data.backend, reason)
+    }
+
+    // Replace every note and notebook with the ones in a backup
+    pub fn restore_backup(&self, id: &str) -> Result<RestoreReport, String> {
+        let key = self.vault.lock().unwrap().key();
+        if key.is_locked() {
+            throw!("The vault is locked");
+        }
+        let mut data = self.notes.lock().unwrap();
+        let mut notebooks = self.notebooks.lock().unwrap();
+        let data_path = data.data_path.clone();
+        let report = self.backups.restore(id, &data_path, &mut data.backend)?;
+
+        let index = std::mem::take(&mut data.search);
+        data.clear();
+        data.reload();
+        data.attach_search_index(index);
+        *notebooks = Notebooks::new(&self.data_path, key);
+        Ok(report)
+    }
+
+    pub fn watch_dir(&self) -> Option<PathBuf> {
+        let data = self.notes.lock().unwrap();
+        data.backend.watch_dir()
+    }
+
+    pub fn reload_paths(&self, paths: &[PathBuf]) -> NotesChanged {
+        let mut data = self.notes.lock().unwrap();
+        data.reload_paths(paths)
+    }
+
+    // Write anything kept in memory only back to disk, called when the app exits
+    pub fn persist(&self) {
+        let mut data = self.notes.lock().unwrap();
+        if let Err(e) = data.search.persist() {
+            eprintln!("Error saving search index: {}", e);
+        }
+        if let Err(e) = data.summaries.persist() {
+            eprintln!("Error saving summary index: {}", e);
+        }
     }
 }
 
 pub struct Data(pub Mutex<Store>);
 
 #[tauri::command]
-pub fn save_file(
-    file_name: String,
-    content: String,
-    uuid: Option<Uuid>,
-    data: State<'_, Data>,
-) -> Result<Value, String> {
+pub fn save_file(note: Note, uuid: Option<Uuid>, data: State<'_, Data>) -> Result<Value, String> {
     let cache = data.0.lock().unwrap();
+    if cache.is_locked() {
+        throw!("The vault is locked");
+    }
 
-    match cache.has_key(uuid) {
-        true => cache.set(InsertKind::Uuid(uuid.unwrap()), content),
-        _ => {
-            cache.set(InsertKind::String(file_name), content.clone());
-        }
+    let uuid = match cache.has_key(uuid) {
+        true => cache.set(InsertKind::Uuid(uuid.unwrap()), note)?,
+        _ => cache.set(InsertKind::String(note.title.clone()), note)?,
     };
 
-    to_json(&cache.get_all())
+    to_json(&cache.get(&uuid)?)
 }
 
 #[tauri::command]
-pub fn get_files(data: State<'_, Data>) -> Result<Value, String> {
+pub fn get_files(filter: Option<String>, data: State<'_, Data>) -> Result<Value, String> {
     let cache = data.0.lock().unwrap();
 
-    to_json(&cache.get_all())
+    match filter {
+        Some(filter) if !filter.trim().is_empty() => to_json(&cache.get_filtered(&filter)?),
+        _ => to_json(&cache.get_headers()),
+    }
+}
+
+#[tauri::command]
+pub fn get_note(uuid: Uuid, data: State<'_, Data>) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+
+    Ok(to_json(&cache.get(&uuid)?)?)
+}
+
+#[tauri::command]
+pub fn get_notes(uuids: Vec<Uuid>, data: State<'_, Data>) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+
+    Ok(to_json(&cache.get_many(&uuids)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::backend::files::FileBackend;
+
+    fn app_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("notes-{}-{}", name, Uuid::new_v4()))
+    }
+
+    fn open(app_dir: &PathBuf, settings: &Settings) -> Store {
+        let data_path = AppData {
+            app_dir: app_dir.to_path_buf(),
+            data_dir: app_dir.join("data"),
+            trash_dir: app_dir.join("trash"),
+            revisions_dir: app_dir.join("revisions"),
+        };
+        let backend = Box::new(FileBackend::new(
+            &data_path.data_dir,
+            settings.storage_format,
+        ));
+        let vault = Vault::new(app_dir, settings.vault);
+        Store::new(data_path, settings, vault, backend)
+    }
+
+    #[test]
+    fn boot_catches_up_with_bodies_edited_outside_the_app() {
+        let app_dir = app_dir("edited-outside");
+        let settings = Settings {
+            storage_format: StorageFormat::Markdown,
+            ..Default::default()
+        };
+        let store = open(&app_dir, &settings);
+        let uuid = store
+            .set(
+                InsertKind::String("Animals".to_string()),
+                Note::new("Animals", "zebra stripes"),
+            )
+            .unwrap();
+        store.persist();
+        let file_path = store.get(&uuid).unwrap().file_path;
+        drop(store);
+
+        // The front matter, `modified_at` included, is left as it was
+        let text = std::fs::read_to_string(&file_path).unwrap();
+        std::fs::write(&file_path, text.replace("zebra stripes", "giraffe spots")).unwrap();
+
+        let store = open(&app_dir, &settings);
+        assert_eq!(store.search("giraffe", 10).unwrap().len(), 1);
+        assert_eq!(store.search("zebra", 10).unwrap().len(), 0);
+        let page = store.list_notes(SortBy::Modified, None, 10).unwrap();
+        assert_eq!(page.notes[0].excerpt, "giraffe spots");
+        assert_eq!(store.get(&uuid).unwrap().content.body, "giraffe spots");
+        std::fs::remove_dir_all(&app_dir).unwrap();
+    }
+    #[test]
+    fn renaming_a_note_follows_file_links_in_any_case() {
+        let app_dir = app_dir("rename-file-links");
+        let settings = Settings {
+            storage_format: StorageFormat::Markdown,
+            ..Default::default()
+        };
+        let store = open(&app_dir, &settings);
+        let create = |title: &str, body: &str| {
+            store
+                .set(
+                    InsertKind::String(title.to_string()),
+                    Note::new(title, body),
+                )
+                .unwrap()
+        };
+        let foo = create("Foo", "target");
+        assert!(store.get(&foo).unwrap().file_path.ends_with("foo.md"));
+        let upper = create("Upper", "See [x](Foo.md)");
+        let mixed = create("Mixed", "See [x](FOO.md) and [y](foo.md)");
+
+        store
+            .set(InsertKind::Uuid(foo), Note::new("Bar", "target"))
+            .unwrap();
+        assert_eq!(store.get(&upper).unwrap().content.body, "See [x](bar.md)");
+        assert_eq!(
+            store.get(&mixed).unwrap().content.body,
+            "See [x](bar.md) and [y](bar.md)"
+        );
+        std::fs::remove_dir_all(&app_dir).unwrap();
+    }
+    #[test]
+    fn lookups_of_a_missing_note_are_not_found() {
+        let app_dir = app_dir("not-found");
+        let store = open(&app_dir, &Settings::default());
+        let missing = Uuid::new_v4();
+        let not_found = NoteError::not_found(&missing);
+        assert_eq!(store.get(&missing).unwrap_err(), not_found);
+        assert_eq!(store.add_tag(&missing, "work").unwrap_err(), not_found);
+        assert_eq!(store.render(&missing).unwrap_err(), not_found);
+        assert_eq!(store.get_backlinks(&missing).unwrap_err(), not_found);
+        assert_eq!(store.get_outgoing_links(&missing).unwrap_err(), not_found);
+        assert_eq!(
+            store.lock_note(&missing, Some("pw")).unwrap_err(),
+            not_found
+        );
+        assert_eq!(store.unlock_note(&missing, "pw").unwrap_err(), not_found);
+        assert_eq!(store.restore_revision(&missing, 1).unwrap_err(), not_found);
+        assert_eq!(store.delete(&missing).unwrap_err(), not_found);
+        std::fs::remove_dir_all(&app_dir).unwrap();
+    }
+    #[test]
+    fn list_notes_pages_through_every_note() {
+        let app_dir = app_dir("pages");
+        let store = open(&app_dir, &Settings::default());
+        for title in ["One", "Two", "Three"] {
+            store
+                .set(InsertKind::String(title.to_string()), Note::new(title, ""))
+                .unwrap();
+        }
+        assert!(store.list_notes(SortBy::Title, None, 0).is_err());
+
+        let mut titles = vec![];
+        let mut cursor = None;
+        loop {
+            let page = store
+                .list_notes(SortBy::Title, cursor.as_deref(), 2)
+                .unwrap();
+            titles.extend(page.notes.into_iter().map(|n| n.title));
+            cursor = match page.next_cursor {
+                Some(cursor) => Some(cursor),
+                None => break,
+            };
+        }
+        assert_eq!(titles, vec!["One", "Three", "Two"]);
+        std::fs::remove_dir_all(&app_dir).unwrap();
+    }
 }
@@ -0,0 +1,75 @@
+use crate::core::utils::time::now_millis;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const NOTE_SCHEMA_VERSION: u32 = 1;
+
+// A note as edited by the user. Timestamps are milliseconds since the unix epoch
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Note {
+    pub version: u32,
+    pub title: String,
+    pub body: String,
+    pub tags: Vec<String>,
+    // The notebook this note lives in, if any
+    pub parent: Option<Uuid>,
+    pub created_at: u64,
+    pub modified_at: u64,
+}
+
+// The stringified object the frontend used to store in `NoteFile.content`
+#[derive(Deserialize)]
+struct LegacyContent {
+    #[serde(default)]
+    file_name: String,
+    #[serde(default)]
+    content: String,
+}
+
+impl Note {
+    pub fn new(title: &str, body: &str) -> Self {
+        let now = now_millis();
+        Self {
+            version: NOTE_SCHEMA_VERSION,
+            title: title.to_string(),
+            body: body.to_string(),
+            tags: vec![],
+            parent: None,
+            created_at: now,
+            modified_at: now,
+        }
+    }
+    // Build a note from an unversioned `content` string, which is either a
+    // stringified `{file_name, content}` object or the raw markdown itself
+    pub fn from_legacy(content: &str, timestamp: u64) -> Self {
+        let (title, body) = match serde_json::from_str::<LegacyContent>(content) {
+            Ok(legacy) => (legacy.file_name, legacy.content),
+            Err(_) => (String::new(), content.to_string()),
+        };
+        Self {
+            version: NOTE_SCHEMA_VERSION,
+            title,
+            body,
+            tags: vec![],
+            parent: None,
+            created_at: timestamp,
+            modified_at: timestamp,
+        }
+    }
+}
+
+impl Default for Note {
+    fn default() -> Self {
+        Self {
+            version: NOTE_SCHEMA_VERSION,
+            title: Default::default(),
+            body: Default::default(),
+            tags: Default::default(),
+            parent: Default::default(),
+            created_at: Default::default(),
+            modified_at: Default::default(),
+        }
+    }
+}
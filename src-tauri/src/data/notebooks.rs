@@ -0,0 +1,316 @@
+use crate::core::utils::fs::write_atomically;
+use crate::core::utils::json::to_json;
+use crate::core::utils::time::now_millis;
+use crate::data::error::NoteError;
+use crate::data::vault::VaultKey;
+use crate::data::{Data, NoteFile};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tauri::State;
+use uuid::Uuid;
+
+// Kept in the data directory; hidden so it isn't mistaken for a note
+pub const NOTEBOOKS_FILE: &str = ".notebooks.json";
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct Notebook {
+    pub id: Uuid,
+    pub name: String,
+    pub parent: Option<Uuid>,
+    pub created_at: u64,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct NoteRef {
+    pub uuid: Uuid,
+    pub title: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct NotebookNode {
+    pub id: Uuid,
+    pub name: String,
+    pub notebooks: Vec<NotebookNode>,
+    pub notes: Vec<NoteRef>,
+}
+
+// The top level of the hierarchy: root notebooks and notes outside any notebook
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct NotebookTree {
+    pub notebooks: Vec<NotebookNode>,
+    pub notes: Vec<NoteRef>,
+}
+
+// Notebook names are sealed like the notes when the vault is enabled, and
+// none are loaded while it is locked
+#[derive(Debug, Clone)]
+pub struct Notebooks {
+    pub notebooks_path: PathBuf,
+    entries: HashMap<Uuid, Notebook>,
+    key: VaultKey,
+}
+
+impl Notebooks {
+    pub fn new(data_path: &PathBuf, key: VaultKey) -> Self {
+        let notebooks_path = data_path.join(NOTEBOOKS_FILE);
+        let mut entries = HashMap::new();
+        let notebooks_str = match key.is_locked() {
+            true => None,
+            false => std::fs::read_to_string(&notebooks_path).ok(),
+        };
+        if let Some(notebooks_str) = notebooks_str {
+            let list = serde_json::from_str(&notebooks_str)
+                .map_err(|e| e.to_string())
+                .and_then(|value| key.open_json(value))
+                .and_then(|value| {
+                    serde_json::from_value::<Vec<Notebook>>(value).map_err(|e| e.to_string())
+                });
+            match list {
+                Ok(list) => {
+                    for notebook in list {
+                        entries.insert(notebook.id, notebook);
+                    }
+                }
+                Err(e) => eprintln!("Could not parse notebooks file: {}", e),
+            }
+        }
+        Self {
+            notebooks_path,
+            entries,
+            key,
+        }
+    }
+
+    // Also called when the vault is created, to seal notebooks written before it
+    pub fn save(&self) -> Result<(), String> {
+        write_atomically(
+            &self.notebooks_path,
+            self.key.seal_json(to_json(&self.list())?)?,
+        )
+    }
+
+    pub fn has(&self, id: &Uuid) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Result<Notebook, String> {
+        match self.entries.get(id) {
+            Some(notebook) => Ok(notebook.to_owned()),
+            None => throw!("Notebook {} does not exist", id),
+        }
+    }
+
+    fn check_parent(&self, parent: &Option<Uuid>) -> Result<(), String> {
+        match parent {
+            Some(parent) if !self.has(parent) => throw!("Notebook {} does not exist", parent),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn create(&mut self, name: &str, parent: Option<Uuid>) -> Result<Notebook, String> {
+        self.check_parent(&parent)?;
+        let notebook = Notebook {
+            id: Uuid::new_v4(),
+            name: name.trim().to_string(),
+            parent,
+            created_at: now_millis(),
+        };
+        self.entries.insert(notebook.id, notebook.to_owned());
+        self.save()?;
+        Ok(notebook)
+    }
+
+    pub fn list(&self) -> Vec<Notebook> {
+        let mut list: Vec<Notebook> = self.entries.values().cloned().collect();
+        list.sort_by_key(|n| n.created_at);
+        list
+    }
+
+    // Add notebooks keeping their ids, e.g. from an archive, returning how
+    // many were new. Ones that already exist are left as they are, and a
+    // parent that is nowhere to be found makes a notebook a root one
+    pub fn restore_many(&mut self, notebooks: &[Notebook]) -> Result<usize, String> {
+        let ids: Vec<Uuid> = notebooks.iter().map(|n| n.id).collect();
+        let mut added = 0;
+        for notebook in notebooks {
+            if self.has(&notebook.id) {
+                continue;
+            }
+            let mut notebook = notebook.to_owned();
+            notebook.parent = notebook
+                .parent
+                .filter(|parent| self.has(parent) || ids.contains(parent));
+            self.entries.insert(notebook.id, notebook);
+            added += 1;
+        }
+        match added {
+            0 => Ok(0),
+            added => self.save().map(|_| added),
+        }
+    }
+
+    // A notebook by name, compared case-insensitively, among the children of `parent`
+    pub fn find(&self, name: &str, parent: Option<Uuid>) -> Option<Uuid> {
+        let name = name.trim().to_lowercase();
+        self.entries
+            .values()
+            .find(|n| n.parent == parent && n.name.to_lowercase() == name)
+            .map(|n| n.id)
+    }
+
+    pub fn rename(&mut self, id: &Uuid, name: &str) -> Result<(), String> {
+        match self.entries.get_mut(id) {
+            Some(notebook) => notebook.name = name.trim().to_string(),
+            None => throw!("Notebook {} does not exist", id),
+        }
+        self.save()
+    }
+
+    // Whether a note filed under `parent` is inside `notebook`, directly or in a nested notebook
+    pub fn contains(&self, notebook: &Uuid, parent: Option<Uuid>) -> bool {
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            if current == *notebook {
+                return true;
+            }
+            ancestor = self.entries.get(&current).and_then(|n| n.parent);
+        }
+        false
+    }
+
+    // Move a notebook under a new parent, refusing to move it inside itself
+    pub fn reparent(&mut self, id: &Uuid, parent: Option<Uuid>) -> Result<(), String> {
+        self.get(id)?;
+        self.check_parent(&parent)?;
+
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            if current == *id {
+                throw!("Cannot move a notebook into itself");
+            }
+            ancestor = self.entries.get(&current).and_then(|n| n.parent);
+        }
+
+        if let Some(notebook) = self.entries.get_mut(id) {
+            notebook.parent = parent;
+        }
+        self.save()
+    }
+
+    // Delete a notebook, moving its child notebooks up to its parent
+    pub fn remove(&mut self, id: &Uuid) -> Result<Notebook, String> {
+        let notebook = self.get(id)?;
+        for child in self.entries.values_mut() {
+            if child.parent == Some(*id) {
+                child.parent = notebook.parent;
+            }
+        }
+        self.entries.remove(id);
+        self.save()?;
+        Ok(notebook)
+    }
+
+    pub fn tree(&self, entries: &HashMap<Uuid, NoteFile>) -> NotebookTree {
+        let mut notes_by_parent: HashMap<Option<Uuid>, Vec<NoteRef>> = HashMap::new();
+        for (uuid, note) in entries.iter() {
+            // Notes pointing at a notebook that no longer exists show up at the top level
+            let parent = note.content.parent.filter(|p| self.has(p));
+            notes_by_parent.entry(parent).or_default().push(NoteRef {
+                uuid: *uuid,
+                title: note.content.title.to_owned(),
+            });
+        }
+        for notes in notes_by_parent.values_mut() {
+            notes.sort_by_key(|n| n.title.to_lowercase());
+        }
+
+        NotebookTree {
+            notebooks: self.children(None, &mut notes_by_parent),
+            notes: notes_by_parent.remove(&None).unwrap_or_default(),
+        }
+    }
+
+    fn children(
+        &self,
+        parent: Option<Uuid>,
+        notes_by_parent: &mut HashMap<Option<Uuid>, Vec<NoteRef>>,
+    ) -> Vec<NotebookNode> {
+        let mut children: Vec<&Notebook> = self
+            .entries
+            .values()
+            .filter(|n| n.parent == parent)
+            .collect();
+        children.sort_by_key(|n| n.name.to_lowercase());
+
+        children
+            .into_iter()
+            .map(|n| NotebookNode {
+                id: n.id,
+                name: n.name.to_owned(),
+                notebooks: self.children(Some(n.id), notes_by_parent),
+                notes: notes_by_parent.remove(&Some(n.id)).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+#[tauri::command]
+pub fn create_notebook(
+    name: String,
+    parent: Option<Uuid>,
+    data: State<'_, Data>,
+) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.create_notebook(&name, parent)?)
+}
+
+#[tauri::command]
+pub fn rename_notebook(id: Uuid, name: String, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    cache.rename_notebook(&id, &name)?;
+
+    to_json(&cache.get_notebook_tree())
+}
+
+#[tauri::command]
+pub fn move_notebook(
+    id: Uuid,
+    parent: Option<Uuid>,
+    data: State<'_, Data>,
+) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    cache.move_notebook(&id, parent)?;
+
+    to_json(&cache.get_notebook_tree())
+}
+
+#[tauri::command]
+pub fn delete_notebook(id: Uuid, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    cache.delete_notebook(&id)?;
+
+    to_json(&cache.get_notebook_tree())
+}
+
+#[tauri::command]
+pub fn move_note(
+    uuid: Uuid,
+    notebook: Option<Uuid>,
+    data: State<'_, Data>,
+) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+    cache.move_note(&uuid, notebook)?;
+
+    Ok(to_json(&cache.get_notebook_tree())?)
+}
+
+#[tauri::command]
+pub fn get_notebook_tree(data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.get_notebook_tree())
+}
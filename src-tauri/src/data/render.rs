@@ -0,0 +1,233 @@
+use crate::core::utils::json::to_json;
+use crate::data::error::NoteError;
+use crate::data::markdown::slugify;
+use crate::data::search::escape_html;
+use crate::data::Data;
+
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag};
+use serde_json::Value;
+use std::collections::HashMap;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+use tauri::State;
+use uuid::Uuid;
+
+// Highlighted code is marked up with classes like `hl-keyword`, styled by `highlight_css`
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+
+// Turns note bodies into sanitized HTML. Loading the syntax definitions is
+// slow, so one renderer is kept for the life of the app
+#[derive(Debug)]
+pub struct Renderer {
+    syntax_set: SyntaxSet,
+    sanitizer: ammonia::Builder<'static>,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        let mut sanitizer = ammonia::Builder::default();
+        sanitizer
+            .add_tags(&["input"])
+            .add_tag_attributes("input", &["type", "checked", "disabled"])
+            .add_tag_attributes("div", &["class", "id"])
+            .add_tag_attributes("sup", &["class"])
+            .add_tag_attributes("pre", &["class"])
+            .add_tag_attributes("code", &["class"])
+            .add_tag_attributes("span", &["class"])
+            .add_url_schemes(&["note"]);
+        for heading in ["h1", "h2", "h3", "h4", "h5", "h6"] {
+            sanitizer.add_tag_attributes(heading, &["id"]);
+        }
+        Self {
+            syntax_set: SyntaxSet::load_defaults_newlines(),
+            sanitizer,
+        }
+    }
+}
+
+// What to do with a link or image destination while rendering
+pub enum Destination {
+    Keep,
+    Replace(String),
+    // Render the link text (or an image's alt text) without the link
+    Drop,
+}
+
+impl Renderer {
+    // Render markdown with GFM tables, task lists, strikethrough and
+    // footnotes. Headings get anchors and fenced code is highlighted
+    pub fn render(&self, body: &str) -> String {
+        self.render_with(body, &mut |_| Destination::Keep)
+    }
+
+    // Render markdown, letting `links` rewrite the destination of every link and image
+    pub fn render_with(&self, body: &str, links: &mut dyn FnMut(&str) -> Destination) -> String {
+        let options = Options::ENABLE_TABLES
+            | Options::ENABLE_FOOTNOTES
+            | Options::ENABLE_STRIKETHROUGH
+            | Options::ENABLE_TASKLISTS
+            | Options::ENABLE_HEADING_ATTRIBUTES;
+        let mut anchors = heading_anchors(Parser::new_ext(body, options)).into_iter();
+
+        let mut events = vec![];
+        let mut code: Option<(String, String)> = None;
+        // Whether each open link or image was dropped, to drop its end too
+        let mut dropped = vec![];
+        for event in Parser::new_ext(body, options) {
+            match event {
+                Event::Start(Tag::Link(kind, url, title)) => match links(&url) {
+                    Destination::Keep => {
+                        dropped.push(false);
+                        events.push(Event::Start(Tag::Link(kind, url, title)));
+                    }
+                    Destination::Replace(url) => {
+                        dropped.push(false);
+                        events.push(Event::Start(Tag::Link(kind, url.into(), title)));
+                    }
+                    Destination::Drop => dropped.push(true),
+                },
+                Event::Start(Tag::Image(kind, url, title)) => match links(&url) {
+                    Destination::Keep => {
+                        dropped.push(false);
+                        events.push(Event::Start(Tag::Image(kind, url, title)));
+                    }
+                    Destination::Replace(url) => {
+                        dropped.push(false);
+                        events.push(Event::Start(Tag::Image(kind, url.into(), title)));
+                    }
+                    Destination::Drop => dropped.push(true),
+                },
+                Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => {
+                    if !dropped.pop().unwrap_or(false) {
+                        events.push(event);
+                    }
+                }
+                Event::Start(Tag::Heading(level, _, _)) => {
+                    let anchor = anchors.next().unwrap_or_default();
+                    events.push(Event::Html(CowStr::from(format!(
+                        "<{} id=\"{}\">",
+                        level,
+                        escape_html(&anchor)
+                    ))));
+                }
+                Event::End(Tag::Heading(level, _, _)) => {
+                    events.push(Event::Html(CowStr::from(format!("</{}>\n", level))));
+                }
+                Event::Start(Tag::CodeBlock(kind)) => {
+                    let language = match kind {
+                        CodeBlockKind::Fenced(info) => {
+                            info.split_whitespace().next().unwrap_or("").to_string()
+                        }
+                        CodeBlockKind::Indented => String::new(),
+                    };
+                    code = Some((language, String::new()));
+                }
+                Event::Text(text) if code.is_some() => {
+                    if let Some((_, source)) = code.as_mut() {
+                        source.push_str(&text);
+                    }
+                }
+                Event::End(Tag::CodeBlock(_)) => {
+                    if let Some((language, source)) = code.take() {
+                        events.push(Event::Html(CowStr::from(
+                            self.highlight(&language, &source),
+                        )));
+                    }
+                }
+                event => events.push(event),
+            }
+        }
+
+        let mut unsafe_html = String::new();
+        html::push_html(&mut unsafe_html, events.into_iter());
+        self.sanitizer.clean(&unsafe_html).to_string()
+    }
+
+    fn highlight(&self, language: &str, source: &str) -> String {
+        let syntax = self
+            .syntax_set
+            .find_syntax_by_token(language)
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntax_set, CLASS_STYLE);
+        for line in LinesWithEndings::from(source) {
+            // Falls back to the plain source if the syntax definition chokes
+            if generator
+                .parse_html_for_line_which_includes_newline(line)
+                .is_err()
+            {
+                return format!("<pre><code>{}</code></pre>\n", escape_html(source));
+            }
+        }
+        let class = match language.is_empty() {
+            true => String::new(),
+            false => format!(" class=\"language-{}\"", escape_html(language)),
+        };
+        format!(
+            "<pre class=\"hl-code\"><code{}>{}</code></pre>\n",
+            class,
+            generator.finalize()
+        )
+    }
+}
+
+// An id for every heading, from its `{#id}` attribute or its text. Repeated
+// headings get a numeric suffix so every anchor is unique
+fn heading_anchors(parser: Parser) -> Vec<String> {
+    let mut anchors = vec![];
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut heading: Option<(Option<String>, String)> = None;
+    for event in parser {
+        match event {
+            Event::Start(Tag::Heading(_, id, _)) => {
+                heading = Some((id.map(|id| id.to_string()), String::new()));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, title)) = heading.as_mut() {
+                    title.push_str(&text);
+                }
+            }
+            Event::End(Tag::Heading(..)) => {
+                if let Some((id, title)) = heading.take() {
+                    let anchor = id.unwrap_or_else(|| slugify(&title));
+                    let count = seen.entry(anchor.to_owned()).or_default();
+                    anchors.push(match *count {
+                        0 => anchor,
+                        n => format!("{}-{}", anchor, n),
+                    });
+                    *count += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    anchors
+}
+
+// The stylesheet for highlighted code in one of syntect's bundled themes
+pub fn highlight_css(theme: &str) -> Result<String, String> {
+    let themes = ThemeSet::load_defaults();
+    let theme = match themes.themes.get(theme) {
+        Some(theme) => theme,
+        None => throw!("Unknown theme {}", theme),
+    };
+    match css_for_theme_with_class_style(theme, CLASS_STYLE) {
+        Ok(css) => Ok(css),
+        Err(e) => throw!("Could not build stylesheet: {}", e),
+    }
+}
+
+#[tauri::command]
+pub fn render_note(uuid: Uuid, data: State<'_, Data>) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+
+    Ok(to_json(&cache.render(&uuid)?)?)
+}
+
+#[tauri::command]
+pub fn get_highlight_css(theme: Option<String>) -> Result<Value, String> {
+    to_json(&highlight_css(theme.as_deref().unwrap_or(DEFAULT_THEME))?)
+}
@@ -0,0 +1,448 @@
+use crate::core::utils::fs::write_atomically;
+use crate::core::utils::json::to_json;
+use crate::data::error::NoteError;
+use crate::data::note::Note;
+use crate::data::vault::VaultKey;
+use crate::data::Data;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use similar::{capture_diff_slices, Algorithm, DiffTag, TextDiff};
+use std::fs::read_dir;
+use std::path::PathBuf;
+use tauri::State;
+use uuid::Uuid;
+
+// Saves this close to the newest revision replace it instead of adding one,
+// so typing doesn't leave a revision per autosave
+const COALESCE_MILLIS: u64 = 5 * 60 * 1000;
+// Older revisions are dropped past this many
+const MAX_REVISIONS: usize = 100;
+
+// How to rebuild a revision's body from the lines of the revision after it
+#[derive(Serialize, Debug, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum DeltaOp {
+    Copy { start: usize, len: usize },
+    Insert { lines: Vec<String> },
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct Revision {
+    pub id: u32,
+    pub saved_at: u64,
+    pub title: String,
+    // Empty for the newest revision, whose body is the log's `head`
+    pub delta: Vec<DeltaOp>,
+    // Never replaced by a later save, e.g. the version a restore overwrote
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub kept: bool,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct RevisionInfo {
+    pub id: u32,
+    pub saved_at: u64,
+    pub title: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct RevisionContent {
+    pub id: u32,
+    pub saved_at: u64,
+    pub title: String,
+    pub body: String,
+}
+
+// Every saved version of one note. Only the newest body is stored whole,
+// older ones are reverse deltas so the log stays small
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct RevisionLog {
+    pub uuid: Uuid,
+    pub head: String,
+    pub revisions: Vec<Revision>,
+}
+
+fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+// Describe `older` in terms of the lines of `newer`
+fn reverse_delta(newer: &str, older: &str) -> Vec<DeltaOp> {
+    let newer_lines = split_lines(newer);
+    let older_lines = split_lines(older);
+
+    let mut delta = vec![];
+    for op in capture_diff_slices(Algorithm::Myers, &newer_lines, &older_lines) {
+        let (tag, newer_range, older_range) = op.as_tag_tuple();
+        match tag {
+            DiffTag::Equal => delta.push(DeltaOp::Copy {
+                start: newer_range.start,
+                len: newer_range.len(),
+            }),
+            DiffTag::Insert | DiffTag::Replace => delta.push(DeltaOp::Insert {
+                lines: older_lines[older_range]
+                    .iter()
+                    .map(|l| l.to_string())
+                    .collect(),
+            }),
+            DiffTag::Delete => {}
+        }
+    }
+    delta
+}
+
+fn apply_delta(newer: &str, delta: &[DeltaOp]) -> Result<String, String> {
+    let newer_lines = split_lines(newer);
+    let mut result = String::new();
+    for op in delta {
+        match op {
+            DeltaOp::Copy { start, len } => match newer_lines.get(*start..start + len) {
+                Some(lines) => result.push_str(&lines.concat()),
+                None => throw!("Revision delta is out of range"),
+            },
+            DeltaOp::Insert { lines } => result.push_str(&lines.concat()),
+        }
+    }
+    Ok(result)
+}
+
+impl RevisionLog {
+    pub fn new(uuid: &Uuid) -> Self {
+        Self {
+            uuid: uuid.to_owned(),
+            head: String::new(),
+            revisions: vec![],
+        }
+    }
+    // Add a new newest revision, unless nothing changed since the last one.
+    // A save within `COALESCE_MILLIS` of the newest revision replaces it
+    pub fn push(&mut self, note: &Note) -> Result<(), String> {
+        let count = self.revisions.len();
+        if let Some(last) = self.revisions.last_mut() {
+            if last.title == note.title && self.head == note.body {
+                return Ok(());
+            }
+            let window = last.saved_at..last.saved_at + COALESCE_MILLIS;
+            if !last.kept && window.contains(&note.modified_at) {
+                last.saved_at = note.modified_at;
+                last.title = note.title.to_owned();
+                // The revision before it was stored against the head being replaced
+                if count > 1 {
+                    let before = &mut self.revisions[count - 2];
+                    let body = apply_delta(&self.head, &before.delta)?;
+                    before.delta = reverse_delta(&note.body, &body);
+                }
+                self.head = note.body.to_owned();
+                return Ok(());
+            }
+            last.delta = reverse_delta(&note.body, &self.head);
+        }
+        let id = match self.revisions.last() {
+            Some(last) => last.id + 1,
+            None => 1,
+        };
+        self.revisions.push(Revision {
+            id,
+            saved_at: note.modified_at,
+            title: note.title.to_owned(),
+            delta: vec![],
+            kept: false,
+        });
+        self.head = note.body.to_owned();
+
+        // Each delta only depends on the revision after it, so the oldest can go
+        if self.revisions.len() > MAX_REVISIONS {
+            let excess = self.revisions.len() - MAX_REVISIONS;
+            self.revisions.drain(..excess);
+        }
+        Ok(())
+    }
+    // Keep the newest revision from being replaced by the next save
+    pub fn keep_last(&mut self) {
+        if let Some(last) = self.revisions.last_mut() {
+            last.kept = true;
+        }
+    }
+    // Rebuild a revision by walking the deltas back from the head
+    pub fn get(&self, id: u32) -> Result<RevisionContent, String> {
+        let mut body = self.head.to_owned();
+        for (i, revision) in self.revisions.iter().rev().enumerate() {
+            if i > 0 {
+                body = apply_delta(&body, &revision.delta)?;
+            }
+            if revision.id == id {
+                return Ok(RevisionContent {
+                    id,
+                    saved_at: revision.saved_at,
+                    title: revision.title.to_owned(),
+                    body,
+                });
+            }
+        }
+        throw!("Revision {} of note {} does not exist", id, self.uuid)
+    }
+
+    pub fn list(&self) -> Vec<RevisionInfo> {
+        self.revisions
+            .iter()
+            .rev()
+            .map(|r| RevisionInfo {
+                id: r.id,
+                saved_at: r.saved_at,
+                title: r.title.to_owned(),
+            })
+            .collect()
+    }
+}
+
+// Revision logs, one file per note in the revisions directory
+#[derive(Debug, Clone, Default)]
+pub struct Revisions {
+    pub revisions_path: PathBuf,
+    key: VaultKey,
+}
+
+impl Revisions {
+    pub fn new(revisions_path: &PathBuf, key: VaultKey) -> Self {
+        Self {
+            revisions_path: revisions_path.to_path_buf(),
+            key,
+        }
+    }
+
+    fn log_path(&self, uuid: &Uuid) -> PathBuf {
+        self.revisions_path.join(format!("{}.json", uuid))
+    }
+
+    pub fn load(&self, uuid: &Uuid) -> Result<RevisionLog, String> {
+        let log_str = match std::fs::read_to_string(self.log_path(uuid)) {
+            Ok(log_str) => log_str,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(RevisionLog::new(uuid))
+            }
+            Err(e) => throw!("{}", e.to_string()),
+        };
+        let log = serde_json::from_str(&log_str)
+            .map_err(|e| e.to_string())
+            .and_then(|value| self.key.open_json(value))
+            .and_then(|value| serde_json::from_value(value).map_err(|e| e.to_string()));
+        match log {
+            Ok(log) => Ok(log),
+            Err(e) => throw!("Could not parse revisions of note {}: {}", uuid, e),
+        }
+    }
+
+    fn save(&self, uuid: &Uuid, log: &RevisionLog) -> Result<(), String> {
+        write_atomically(&self.log_path(uuid), self.key.seal_json(to_json(log)?)?)
+    }
+
+    // Record a save. `previous` seeds the log for notes saved before history was kept
+    pub fn record(&self, uuid: &Uuid, previous: Option<&Note>, note: &Note) -> Result<(), String> {
+        let mut log = self.load(uuid)?;
+        if log.revisions.is_empty() {
+            if let Some(previous) = previous {
+                log.push(previous)?;
+                log.keep_last();
+            }
+        }
+        log.push(note)?;
+        self.save(uuid, &log)
+    }
+
+    // Keep the newest stored revision, e.g. before a restore overwrites it
+    pub fn keep(&self, uuid: &Uuid) -> Result<(), String> {
+        let mut log = self.load(uuid)?;
+        log.keep_last();
+        self.save(uuid, &log)
+    }
+
+    // Rewrite every log, so that ones written before the vault existed get encrypted
+    pub fn rewrite_all(&self) -> Result<(), String> {
+        let entries = match read_dir(&self.revisions_path) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(()),
+        };
+        for entry in entries.flatten() {
+            let uuid = entry
+                .path()
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| Uuid::parse_str(stem).ok());
+            if let Some(uuid) = uuid {
+                let log = self.load(&uuid)?;
+                self.save(&uuid, &log)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, uuid: &Uuid) -> Result<(), String> {
+        match std::fs::remove_file(self.log_path(uuid)) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => throw!("Error removing revisions of note {}: {}", uuid, e),
+        }
+    }
+    // Line-level unified diff between two revisions
+    pub fn diff(&self, uuid: &Uuid, from: u32, to: u32) -> Result<String, String> {
+        let log = self.load(uuid)?;
+        let old = log.get(from)?;
+        let new = log.get(to)?;
+        let diff = TextDiff::from_lines(&old.body, &new.body)
+            .unified_diff()
+            .context_radius(3)
+            .header(&format!("revision {}", from), &format!("revision {}", to))
+            .to_string();
+        Ok(diff)
+    }
+}
+
+#[tauri::command]
+pub fn list_revisions(uuid: Uuid, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.revisions.load(&uuid)?.list())
+}
+
+#[tauri::command]
+pub fn get_revision(uuid: Uuid, id: u32, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.revisions.load(&uuid)?.get(id)?)
+}
+
+#[tauri::command]
+pub fn diff_revisions(
+    uuid: Uuid,
+    from: u32,
+    to: u32,
+    data: State<'_, Data>,
+) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.revisions.diff(&uuid, from, to)?)
+}
+
+#[tauri::command]
+pub fn restore_revision(uuid: Uuid, id: u32, data: State<'_, Data>) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+    cache.restore_revision(&uuid, id)?;
+
+    Ok(to_json(&cache.get(&uuid)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(body: &str, modified_at: u64) -> Note {
+        Note {
+            title: "Note".to_string(),
+            body: body.to_string(),
+            modified_at,
+            ..Default::default()
+        }
+    }
+
+    fn bodies(log: &RevisionLog) -> Vec<String> {
+        log.list()
+            .iter()
+            .rev()
+            .map(|r| log.get(r.id).unwrap().body)
+            .collect()
+    }
+
+    fn round_trip(older: &str, newer: &str) {
+        let delta = reverse_delta(newer, older);
+        assert_eq!(apply_delta(newer, &delta).unwrap(), older);
+    }
+
+    #[test]
+    fn reverse_delta_restores_the_older_body() {
+        round_trip("a\nb\nc\n", "a\nB\nc\n");
+        round_trip("a\nb\nc\n", "x\ny\n");
+        round_trip("a\nb\n", "a\nb\nc\nd\n");
+        round_trip("a\nb\nc\nd\n", "b\nd\n");
+        round_trip("same\n", "same\n");
+    }
+
+    #[test]
+    fn reverse_delta_handles_empty_bodies() {
+        round_trip("", "");
+        round_trip("", "a\nb\n");
+        round_trip("a\nb\n", "");
+        round_trip("\n\n", "");
+    }
+
+    #[test]
+    fn reverse_delta_keeps_missing_trailing_newlines() {
+        round_trip("a\nb", "a\nb\n");
+        round_trip("a\nb\n", "a\nb");
+        round_trip("no newline", "no newline at all");
+        round_trip("a\r\nb\r\n", "a\nb\n");
+    }
+
+    #[test]
+    fn reverse_delta_handles_unicode_bodies() {
+        round_trip("héllo\nwörld\n", "héllo\nWörld\n");
+        round_trip("日本語\n🙂 emoji\n", "日本語\n");
+        round_trip("", "ÄK\n\u{200d}👩‍💻\n");
+        round_trip("İstanbul", "istanbul");
+    }
+
+    #[test]
+    fn apply_delta_rejects_out_of_range_copies() {
+        let delta = vec![DeltaOp::Copy { start: 1, len: 2 }];
+        assert!(apply_delta("a\nb\n", &delta).is_err());
+    }
+
+    #[test]
+    fn get_walks_every_delta_back_from_the_head() {
+        let versions = ["", "a\n", "a\nb", "ü\nb\n", "", "🙂\n"];
+        let mut log = RevisionLog::new(&Uuid::new_v4());
+        for (i, body) in versions.iter().enumerate() {
+            log.push(&note(body, i as u64 * COALESCE_MILLIS)).unwrap();
+        }
+        assert_eq!(bodies(&log), versions);
+    }
+
+    #[test]
+    fn push_coalesces_saves_within_the_window() {
+        let mut log = RevisionLog::new(&Uuid::new_v4());
+        log.push(&note("a\n", 0)).unwrap();
+        log.push(&note("a\nb\n", COALESCE_MILLIS)).unwrap();
+        log.push(&note("a\nb\nc\n", COALESCE_MILLIS + 1000))
+            .unwrap();
+        log.push(&note("b\nc\nd\n", COALESCE_MILLIS + 2000))
+            .unwrap();
+        assert_eq!(bodies(&log), vec!["a\n", "b\nc\nd\n"]);
+        assert_eq!(log.revisions[1].saved_at, COALESCE_MILLIS + 2000);
+
+        log.push(&note("e\n", 3 * COALESCE_MILLIS)).unwrap();
+        assert_eq!(bodies(&log), vec!["a\n", "b\nc\nd\n", "e\n"]);
+    }
+
+    #[test]
+    fn push_never_replaces_a_kept_revision() {
+        let mut log = RevisionLog::new(&Uuid::new_v4());
+        log.push(&note("before\n", 0)).unwrap();
+        log.keep_last();
+        log.push(&note("after\n", 1)).unwrap();
+        log.push(&note("after edit\n", 2)).unwrap();
+        assert_eq!(bodies(&log), vec!["before\n", "after edit\n"]);
+    }
+
+    #[test]
+    fn push_drops_the_oldest_revisions_past_the_cap() {
+        let mut log = RevisionLog::new(&Uuid::new_v4());
+        for i in 0..MAX_REVISIONS as u64 + 10 {
+            log.push(&note(&format!("line {}\n", i), i * COALESCE_MILLIS))
+                .unwrap();
+        }
+        assert_eq!(log.revisions.len(), MAX_REVISIONS);
+        assert_eq!(log.revisions[0].id, 11);
+        assert_eq!(log.get(11).unwrap().body, "line 10\n");
+        assert!(log.get(10).is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::core::utils::fs::write_atomically;
+use crate::core::utils::json::to_json;
+use crate::data::bodies::FileStamp;
+use crate::data::{Data, NoteFile};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tauri::State;
+use uuid::Uuid;
+
+// Bump when the persisted layout changes so old indexes get rebuilt
+const INDEX_VERSION: u32 = 2;
+pub const TITLE_WEIGHT: f64 = 3.0;
+const SNIPPET_CONTEXT: usize = 40;
+const SNIPPET_LENGTH: usize = 160;
+
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+pub struct Posting {
+    pub title_tf: u32,
+    pub body_tf: u32,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+pub struct IndexedDoc {
+    pub modified_at: u64,
+    pub stamp: Option<FileStamp>,
+    pub terms: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct SearchHit {
+    pub uuid: Uuid,
+    pub title: String,
+    pub score: f64,
+    pub snippet: String,
+}
+
+// Inverted index over note titles and bodies
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+pub struct SearchIndex {
+    version: u32,
+    #[serde(skip)]
+    pub index_path: PathBuf,
+    #[serde(skip)]
+    dirty: bool,
+    postings: HashMap<String, HashMap<Uuid, Posting>>,
+    docs: HashMap<Uuid, IndexedDoc>,
+}
+
+// Split text into lowercase words, keeping the char range each one came from
+pub fn tokenize(text: &str) -> Vec<(String, usize, usize)> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut start = 0;
+    for (i, c) in text.chars().enumerate() {
+        if c.is_alphanumeric() {
+            if current.is_empty() {
+                start = i;
+            }
+            current.extend(c.to_lowercase());
+        } else if !current.is_empty() {
+            tokens.push((std::mem::take(&mut current), start, i));
+        }
+    }
+    if !current.is_empty() {
+        tokens.push((current, start, text.chars().count()));
+    }
+    tokens
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+impl SearchIndex {
+    pub fn new(index_path: &PathBuf) -> Self {
+        Self {
+            version: INDEX_VERSION,
+            index_path: index_path.to_path_buf(),
+            ..Default::default()
+        }
+    }
+    // Load a previously persisted index, or start an empty one
+    pub fn load(index_path: &PathBuf) -> Self {
+        let index = match std::fs::read_to_string(index_path) {
+            Ok(index_str) => serde_json::from_str::<SearchIndex>(&index_str).ok(),
+            Err(_) => None,
+        };
+        match index {
+            Some(mut index) if index.version == INDEX_VERSION => {
+                index.index_path = index_path.to_path_buf();
+                index
+            }
+            _ => Self::new(index_path),
+        }
+    }
+    // Bring the index in line with the notes actually on disk. `entries`
+    // only need their headers; `load` gives the content of outdated notes
+    pub fn reconcile(
+        &mut self,
+        entries: &HashMap<Uuid, NoteFile>,
+        load: &dyn Fn(&Uuid) -> Option<NoteFile>,
+    ) {
+        let stale: Vec<Uuid> = self
+            .docs
+            .keys()
+            .filter(|uuid| !entries.contains_key(uuid))
+            .copied()
+            .collect();
+        for uuid in stale {
+            self.remove(&uuid);
+        }
+        for (uuid, note) in entries.iter() {
+            let is_current = match self.docs.get(uuid) {
+                Some(doc) => {
+                    doc.modified_at == note.content.modified_at
+                        && doc.stamp == FileStamp::of(&note.file_path)
+                }
+                None => false,
+            };
+            if !is_current {
+                if let Some(note) = load(uuid) {
+                    self.update(uuid, &note);
+                }
+            }
+        }
+    }
+
+    pub fn update(&mut self, uuid: &Uuid, note: &NoteFile) {
+        self.remove(uuid);
+        let stamp = FileStamp::of(&note.file_path);
+        let note = &note.content;
+
+        let mut postings: HashMap<String, Posting> = HashMap::new();
+        for (term, _, _) in tokenize(&note.title) {
+            postings.entry(term).or_default().title_tf += 1;
+        }
+        for (term, _, _) in tokenize(&note.body) {
+            postings.entry(term).or_default().body_tf += 1;
+        }
+
+        let terms = postings.keys().cloned().collect();
+        for (term, posting) in postings {
+            self.postings
+                .entry(term)
+                .or_default()
+                .insert(*uuid, posting);
+        }
+        self.docs.insert(
+            *uuid,
+            IndexedDoc {
+                modified_at: note.modified_at,
+                stamp,
+                terms,
+            },
+        );
+        self.dirty = true;
+    }
+
+    pub fn remove(&mut self, uuid: &Uuid) {
+        if let Some(doc) = self.docs.remove(uuid) {
+            for term in doc.terms {
+                if let Some(postings) = self.postings.get_mut(&term) {
+                    postings.remove(uuid);
+                    if postings.is_empty() {
+                        self.postings.remove(&term);
+                    }
+                }
+            }
+            self.dirty = true;
+        }
+    }
+
+    // Write the index to disk if it changed since it was loaded
+    pub fn persist(&mut self) -> Result<(), String> {
+        // Indexes without a path are kept in memory only
+        if !self.dirty || self.index_path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        write_atomically(&self.index_path, to_json(self)?)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    // Expand the query into index terms. The last word also matches as a prefix,
+    // so results show up while the user is still typing
+    fn query_terms(&self, query: &str) -> Vec<String> {
+        let words: Vec<String> = tokenize(query).into_iter().map(|(t, _, _)| t).collect();
+        let mut terms = vec![];
+        for (i, word) in words.iter().enumerate() {
+            if i == words.len() - 1 {
+                for term in self.postings.keys() {
+                    if term.starts_with(word.as_str()) {
+                        terms.push(term.to_owned());
+                    }
+                }
+            } else if self.postings.contains_key(word) {
+                terms.push(word.to_owned());
+            }
+        }
+        terms.sort();
+        terms.dedup();
+        terms
+    }
+
+    // Rank notes by tf-idf over the query terms, with matches in the title weighted higher
+    pub fn search(
+        &self,
+        query: &str,
+        entries: &HashMap<Uuid, NoteFile>,
+        body: &dyn Fn(&Uuid) -> Option<String>,
+        limit: usize,
+    ) -> Vec<SearchHit> {
+        let terms = self.query_terms(query);
+        let doc_count = self.docs.len() as f64;
+
+        let mut scores: HashMap<Uuid, f64> = HashMap::new();
+        for term in terms.iter() {
+            let postings = match self.postings.get(term) {
+                Some(postings) => postings,
+                None => continue,
+            };
+            let idf = (1.0 + doc_count / postings.len() as f64).ln();
+            for (uuid, posting) in postings.iter() {
+                let tf = posting.title_tf as f64 * TITLE_WEIGHT + posting.body_tf as f64;
+                *scores.entry(*uuid).or_default() += (1.0 + tf.ln()) * idf;
+            }
+        }
+
+        let mut ranked: Vec<(Uuid, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        ranked
+            .into_iter()
+            .filter_map(|(uuid, score)| {
+                let note = entries.get(&uuid)?;
+                Some(SearchHit {
+                    uuid,
+                    title: note.content.title.to_owned(),
+                    score,
+                    snippet: snippet(&body(&uuid)?, &terms),
+                })
+            })
+            .take(limit)
+            .collect()
+    }
+}
+
+// Cut a window of the body around the first match, wrapping every match in `<mark>`
+fn snippet(body: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = body.chars().collect();
+    let matches: Vec<(usize, usize)> = tokenize(body)
+        .into_iter()
+        .filter(|(t, _, _)| terms.contains(t))
+        .map(|(_, start, end)| (start, end))
+        .collect();
+
+    let start = match matches.first() {
+        Some((first, _)) => first.saturating_sub(SNIPPET_CONTEXT),
+        None => 0,
+    };
+    let end = (start + SNIPPET_LENGTH).min(chars.len());
+
+    let mut result = String::new();
+    if start > 0 {
+        result.push('…');
+    }
+    let mut cursor = start;
+    for (match_start, match_end) in matches {
+        if match_start < start || match_end > end {
+            continue;
+        }
+        result.push_str(&escape_html(
+            &chars[cursor..match_start].iter().collect::<String>(),
+        ));
+        result.push_str("<mark>");
+        result.push_str(&escape_html(
+            &chars[match_start..match_end].iter().collect::<String>(),
+        ));
+        result.push_str("</mark>");
+        cursor = match_end;
+    }
+    result.push_str(&escape_html(&chars[cursor..end].iter().collect::<String>()));
+    if end < chars.len() {
+        result.push('…');
+    }
+    result
+}
+
+#[tauri::command]
+pub fn search_notes(
+    query: String,
+    limit: Option<usize>,
+    data: State<'_, Data>,
+) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.search(&query, limit.unwrap_or(50))?)
+}
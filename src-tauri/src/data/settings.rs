@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+// How new and edited notes are written to the data directory
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageFormat {
+    // `<uuid>.json` files wrapping the note
+    Json,
+    // `<title>.md` files with YAML front matter
+    Markdown,
+}
+
+impl Default for StorageFormat {
+    fn default() -> Self {
+        StorageFormat::Json
+    }
+}
+
+// Where notes are stored, picked once at startup
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    // One file per note in the data directory, written in `storage_format`
+    Files,
+    // A single `notes.sqlite3` database in the app directory
+    Sqlite,
+    // Nothing is written to disk; notes are lost when the app exits
+    Memory,
+}
+
+impl Default for StorageBackend {
+    fn default() -> Self {
+        StorageBackend::Files
+    }
+}
+
+// User settings, read from `settings.json` in the app directory
+#[derive(Serialize, Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    // How long deleted notes are kept in the trash before being purged
+    pub trash_retention_days: u64,
+    pub storage_format: StorageFormat,
+    pub storage_backend: StorageBackend,
+    // Encrypt notes with a passphrase. They stay hidden until `unlock_vault`
+    pub vault: bool,
+    // Hours between automatic backups of the data directory, 0 to turn them off
+    pub backup_interval_hours: u64,
+    // How many days, and weeks, to keep the latest backup of
+    pub backup_keep_daily: usize,
+    pub backup_keep_weekly: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            trash_retention_days: 30,
+            storage_format: StorageFormat::default(),
+            storage_backend: StorageBackend::default(),
+            vault: false,
+            backup_interval_hours: 24,
+            backup_keep_daily: 7,
+            backup_keep_weekly: 4,
+        }
+    }
+}
+
+impl Settings {
+    // Load the settings file, falling back to the defaults when it is missing or invalid
+    pub fn load(app_dir: &PathBuf) -> Self {
+        let path = app_dir.join("settings.json");
+        match std::fs::read_to_string(&path) {
+            Ok(settings_str) => match serde_json::from_str(&settings_str) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    eprintln!("Could not parse settings file: {}", e);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use crate::core::utils::fs::{ensure_parent_exists, write_string_atomically};
+use crate::core::utils::json::to_json;
+use crate::data::links::{parse_destination, wiki_to_markdown, LinkIndex};
+use crate::data::markdown::{file_names, slugify};
+use crate::data::note::Note;
+use crate::data::render::{highlight_css, Destination, Renderer, DEFAULT_THEME};
+use crate::data::search::escape_html;
+use crate::data::{Data, NoteFilter};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, PathBuf};
+use tauri::State;
+use uuid::Uuid;
+
+// The same stylesheet the app renders notes with, so exported pages look alike
+const MARKDOWN_CSS: &str = include_str!("../../../src/markdown.css");
+const LAYOUT_CSS: &str = "
+body { margin: 0; }
+.site { max-width: 860px; margin: 0 auto; padding: 32px 24px; box-sizing: border-box; }
+.site nav { margin-bottom: 24px; font-size: 14px; }
+.site .tags a { margin-right: 8px; }
+.site .backlinks { margin-top: 48px; border-top: 1px solid var(--color-border-default); }
+";
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct SiteReport {
+    pub out_dir: PathBuf,
+    pub notes: usize,
+    pub tags: usize,
+    pub attachments: usize,
+}
+
+// A local file a note refers to, relative to the data directory. Paths that
+// leave the data directory are not followed
+fn attachment_path(data_path: &PathBuf, destination: &str) -> Option<PathBuf> {
+    if destination.is_empty() || destination.starts_with('#') || destination.contains(':') {
+        return None;
+    }
+    let relative = PathBuf::from(destination.split(['#', '?']).next().unwrap_or(""));
+    let is_plain = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    let source = data_path.join(&relative);
+    match is_plain && source.is_file() {
+        true => Some(relative),
+        false => None,
+    }
+}
+
+fn page(title: &str, root: &str, content: &str) -> String {
+    format!(
+        concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+            "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n",
+            "<title>{}</title>\n<link rel=\"stylesheet\" href=\"{}style.css\">\n</head>\n",
+            "<body>\n<div class=\"site markdown-body\">\n{}</div>\n</body>\n</html>\n"
+        ),
+        escape_html(title),
+        root,
+        content
+    )
+}
+
+fn note_list(notes: &[(Uuid, Note)], names: &HashMap<Uuid, String>, dir: &str) -> String {
+    let mut list = String::from("<ul>\n");
+    for (uuid, note) in notes.iter() {
+        list.push_str(&format!(
+            "<li><a href=\"{}{}\">{}</a></li>\n",
+            dir,
+            names[uuid],
+            escape_html(&note.title)
+        ));
+    }
+    list.push_str("</ul>\n");
+    list
+}
+
+// A page name per tag. Tags that slug alike, like `c++` and `c#`, get a
+// numeric suffix in the order they come
+fn tag_file_names<'a>(tags: impl Iterator<Item = &'a String>) -> HashMap<&'a String, String> {
+    let mut names = HashMap::new();
+    let mut taken = HashSet::new();
+    for tag in tags {
+        let slug = slugify(tag);
+        let mut name = format!("{}.html", slug);
+        let mut n = 1;
+        while !taken.insert(name.to_owned()) {
+            n += 1;
+            name = format!("{}-{}.html", slug, n);
+        }
+        names.insert(tag, name);
+    }
+    names
+}
+
+// Write `notes` to `out_dir` as a static site: `index.html`, a page per note
+// in `notes/`, a page per tag in `tags/`, the files notes refer to in
+// `attachments/` and `style.css`. Links to notes left out of the export are
+// rendered as plain text
+pub fn write_site(
+    notes: &[(Uuid, Note)],
+    links: &LinkIndex,
+    renderer: &Renderer,
+    data_path: &PathBuf,
+    out_dir: &PathBuf,
+    title: &str,
+) -> Result<SiteReport, String> {
+    let mut notes = notes.to_vec();
+    notes.sort_by(|(a_uuid, a), (b_uuid, b)| {
+        (a.title.to_lowercase(), a_uuid).cmp(&(b.title.to_lowercase(), b_uuid))
+    });
+    let names = file_names(&notes, "html");
+    let mut tags: BTreeMap<String, Vec<(Uuid, Note)>> = BTreeMap::new();
+    for (uuid, note) in notes.iter() {
+        for tag in note.tags.iter() {
+            tags.entry(tag.to_owned())
+                .or_default()
+                .push((*uuid, note.to_owned()));
+        }
+    }
+    let tag_names = tag_file_names(tags.keys());
+
+    let mut attachments: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for (uuid, note) in notes.iter() {
+        let body = wiki_to_markdown(&note.body, links);
+        let html = renderer.render_with(&body, &mut |destination| {
+            if let Some(target) = parse_destination(destination) {
+                let fragment = destination
+                    .split_once('#')
+                    .map(|(_, f)| format!("#{}", f))
+                    .unwrap_or_default();
+                return match links.resolve(&target).and_then(|t| names.get(&t)) {
+                    Some(name) => Destination::Replace(format!("{}{}", name, fragment)),
+                    None => Destination::Drop,
+                };
+            }
+            match attachment_path(data_path, destination) {
+                Some(relative) => {
+                    let url: Vec<String> = relative
+                        .components()
+                        .filter(|c| matches!(c, Component::Normal(_)))
+                        .map(|c| c.as_os_str().to_string_lossy().to_string())
+                        .collect();
+                    attachments.insert(
+                        data_path.join(&relative),
+                        out_dir.join("attachments").join(&relative),
+                    );
+                    Destination::Replace(format!("../attachments/{}", url.join("/")))
+                }
+                None => Destination::Keep,
+            }
+        });
+
+        let mut content = String::from("<nav><a href=\"../index.html\">All notes</a></nav>\n");
+        content.push_str(&format!("<h1>{}</h1>\n", escape_html(&note.title)));
+        if !note.tags.is_empty() {
+            content.push_str("<p class=\"tags\">");
+            for tag in note.tags.iter() {
+                content.push_str(&format!(
+                    "<a href=\"../tags/{}\">#{}</a>",
+                    tag_names[tag],
+                    escape_html(tag)
+                ));
+            }
+            content.push_str("</p>\n");
+        }
+        content.push_str(&html);
+        let backlinks: Vec<(Uuid, Note)> = notes
+            .iter()
+            .filter(|(other, _)| links.backlinks(uuid).contains(other))
+            .cloned()
+            .collect();
+        if !backlinks.is_empty() {
+            content.push_str("<section class=\"backlinks\">\n<h2>Linked from</h2>\n");
+            content.push_str(&note_list(&backlinks, &names, ""));
+            content.push_str("</section>\n");
+        }
+        write_string_atomically(
+            &out_dir.join("notes").join(&names[uuid]),
+            &page(&note.title, "../", &content),
+        )?;
+    }
+
+    for (tag, tagged) in tags.iter() {
+        let content = format!(
+            "<nav><a href=\"../index.html\">All notes</a></nav>\n<h1>#{}</h1>\n{}",
+            escape_html(tag),
+            note_list(tagged, &names, "../notes/")
+        );
+        write_string_atomically(
+            &out_dir.join("tags").join(&tag_names[tag]),
+            &page(&format!("#{}", tag), "../", &content),
+        )?;
+    }
+
+    let mut index = format!("<h1>{}</h1>\n", escape_html(title));
+    index.push_str(&note_list(&notes, &names, "notes/"));
+    if !tags.is_empty() {
+        index.push_str("<h2>Tags</h2>\n<p class=\"tags\">");
+        for (tag, tagged) in tags.iter() {
+            index.push_str(&format!(
+                "<a href=\"tags/{}\">#{}</a> ({}) ",
+                tag_names[tag],
+                escape_html(tag),
+                tagged.len()
+            ));
+        }
+        index.push_str("</p>\n");
+    }
+    write_string_atomically(&out_dir.join("index.html"), &page(title, "", &index))?;
+
+    for (from, to) in attachments.iter() {
+        ensure_parent_exists(to)?;
+        if let Err(e) = std::fs::copy(from, to) {
+            throw!("Error copying {}: {}", from.display(), e);
+        }
+    }
+
+    let css = format!(
+        "{}\n{}\n{}",
+        MARKDOWN_CSS,
+        LAYOUT_CSS,
+        highlight_css(DEFAULT_THEME)?
+    );
+    write_string_atomically(&out_dir.join("style.css"), &css)?;
+
+    Ok(SiteReport {
+        out_dir: out_dir.to_path_buf(),
+        notes: notes.len(),
+        tags: tags.len(),
+        attachments: attachments.len(),
+    })
+}
+
+// Export the notes matching the filters as a static website into `out_dir`.
+// Existing files there are overwritten, anything else is left alone
+#[tauri::command]
+pub fn export_site(
+    out_dir: PathBuf,
+    filter: Option<String>,
+    notebook: Option<Uuid>,
+    title: Option<String>,
+    data: State<'_, Data>,
+) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    let filter = NoteFilter::new(filter.as_deref(), notebook)?;
+    let title = title.unwrap_or_else(|| "Notes".to_string());
+
+    to_json(&cache.export_site(&filter, &out_dir, &title)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_file_names_are_unique() {
+        let tags: Vec<String> = ["c", "c#", "c++", "Work", "work", "!!", "??", "c-2"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        let names = tag_file_names(tags.iter());
+        let name = |tag: &str| names[&tag.to_string()].as_str();
+        assert_eq!(name("c"), "c.html");
+        assert_eq!(name("c#"), "c-2.html");
+        assert_eq!(name("c++"), "c-3.html");
+        assert_eq!(name("Work"), "work.html");
+        assert_eq!(name("work"), "work-2.html");
+        assert_eq!(name("!!"), "untitled.html");
+        assert_eq!(name("??"), "untitled-2.html");
+        // A tag that slugs to a suffixed name takes the next free one
+        assert_eq!(name("c-2"), "c-2-2.html");
+        let unique: HashSet<&String> = names.values().collect();
+        assert_eq!(unique.len(), tags.len());
+    }
+}
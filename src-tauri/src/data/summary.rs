@@ -0,0 +1,296 @@
+use crate::core::utils::fs::write_atomically;
+use crate::core::utils::json::to_json;
+use crate::data::bodies::{digest, FileStamp};
+use crate::data::links::{parse_links, LinkTarget};
+use crate::data::{Data, NoteFile};
+
+use pulldown_cmark::{Event, Parser};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tauri::State;
+use uuid::Uuid;
+
+// Bump when the persisted layout changes so old indexes get rebuilt
+const INDEX_VERSION: u32 = 2;
+const EXCERPT_LENGTH: usize = 160;
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+// What the note list shows, without the body
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct NoteSummary {
+    pub uuid: Uuid,
+    pub title: String,
+    pub excerpt: String,
+    pub tags: Vec<String>,
+    pub created_at: u64,
+    pub modified_at: u64,
+}
+
+impl NoteSummary {
+    // `note` only needs its header; the excerpt comes from the body's `BodyFacts`
+    pub fn new(uuid: &Uuid, note: &NoteFile, facts: &BodyFacts) -> Self {
+        Self {
+            uuid: *uuid,
+            title: note.content.title.to_owned(),
+            excerpt: facts.excerpt.to_owned(),
+            tags: note.content.tags.to_owned(),
+            created_at: note.content.created_at,
+            modified_at: note.content.modified_at,
+        }
+    }
+}
+
+// What the indexes need from a note's body, persisted so that startup only
+// has to read the headers of notes unchanged since
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct BodyFacts {
+    pub modified_at: u64,
+    pub stamp: Option<FileStamp>,
+    pub digest: u64,
+    pub excerpt: String,
+    pub links: Vec<LinkTarget>,
+}
+
+impl BodyFacts {
+    pub fn new(note: &NoteFile) -> Self {
+        Self {
+            modified_at: note.content.modified_at,
+            stamp: FileStamp::of(&note.file_path),
+            digest: digest(&note.content.body),
+            excerpt: excerpt(&note.content.body),
+            links: parse_links(&note.content.body),
+        }
+    }
+}
+
+// The start of a note body as plain text, with the markdown syntax dropped
+pub fn excerpt(body: &str) -> String {
+    let mut words: Vec<String> = vec![];
+    let mut length = 0;
+    for event in Parser::new(body) {
+        let text = match event {
+            Event::Text(text) | Event::Code(text) => text,
+            _ => continue,
+        };
+        for word in text.split_whitespace() {
+            length += word.chars().count() + 1;
+            words.push(word.to_string());
+        }
+        if length > EXCERPT_LENGTH {
+            break;
+        }
+    }
+
+    let excerpt = words.join(" ");
+    match excerpt.chars().count() > EXCERPT_LENGTH {
+        true => {
+            let cut: String = excerpt.chars().take(EXCERPT_LENGTH).collect();
+            format!("{}…", cut.trim_end())
+        }
+        false => excerpt,
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    // Most recently modified first
+    Modified,
+    // Newest first
+    Created,
+    // Alphabetically, ignoring case
+    Title,
+}
+
+impl Default for SortBy {
+    fn default() -> Self {
+        SortBy::Modified
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct NotePage {
+    pub notes: Vec<NoteSummary>,
+    // Pass back to get the page after this one; `None` on the last page
+    pub next_cursor: Option<String>,
+    pub total: usize,
+}
+
+// A summary of every note, kept up to date along with the other indexes.
+// The facts about each body are written to `index_path` when the app exits
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+pub struct SummaryIndex {
+    version: u32,
+    #[serde(skip)]
+    pub index_path: PathBuf,
+    #[serde(skip)]
+    dirty: bool,
+    facts: HashMap<Uuid, BodyFacts>,
+    #[serde(skip)]
+    summaries: HashMap<Uuid, NoteSummary>,
+}
+
+impl SummaryIndex {
+    pub fn new(index_path: &PathBuf) -> Self {
+        Self {
+            version: INDEX_VERSION,
+            index_path: index_path.to_path_buf(),
+            ..Default::default()
+        }
+    }
+    // Load a previously persisted index, or start an empty one
+    pub fn load(index_path: &PathBuf) -> Self {
+        let index = match std::fs::read_to_string(index_path) {
+            Ok(index_str) => serde_json::from_str::<SummaryIndex>(&index_str).ok(),
+            Err(_) => None,
+        };
+        match index {
+            Some(mut index) if index.version == INDEX_VERSION => {
+                index.index_path = index_path.to_path_buf();
+                index
+            }
+            _ => Self::new(index_path),
+        }
+    }
+    // Bring the index in line with the notes actually stored. `entries` only
+    // need their headers; `load` gives the whole note when its facts are outdated
+    pub fn reconcile(
+        &mut self,
+        entries: &HashMap<Uuid, NoteFile>,
+        load: &dyn Fn(&Uuid) -> Option<NoteFile>,
+    ) {
+        let before = self.facts.len();
+        self.facts.retain(|uuid, _| entries.contains_key(uuid));
+        self.dirty |= self.facts.len() != before;
+        self.summaries.clear();
+        for (uuid, note) in entries.iter() {
+            let is_current = match self.facts.get(uuid) {
+                Some(facts) => {
+                    facts.modified_at == note.content.modified_at
+                        && facts.stamp == FileStamp::of(&note.file_path)
+                }
+                None => false,
+            };
+            if !is_current {
+                match load(uuid) {
+                    Some(note) => self.update(uuid, &note),
+                    None => continue,
+                }
+            }
+            if let Some(facts) = self.facts.get(uuid) {
+                let summary = NoteSummary::new(uuid, note, facts);
+                self.summaries.insert(*uuid, summary);
+            }
+        }
+    }
+
+    // `note` has to come with its body
+    pub fn update(&mut self, uuid: &Uuid, note: &NoteFile) {
+        let facts = BodyFacts::new(note);
+        self.summaries
+            .insert(*uuid, NoteSummary::new(uuid, note, &facts));
+        self.facts.insert(*uuid, facts);
+        self.dirty = true;
+    }
+
+    pub fn remove(&mut self, uuid: &Uuid) {
+        self.summaries.remove(uuid);
+        if self.facts.remove(uuid).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    pub fn facts(&self, uuid: &Uuid) -> Option<&BodyFacts> {
+        self.facts.get(uuid)
+    }
+
+    // Write the index to disk if it changed since it was loaded
+    pub fn persist(&mut self) -> Result<(), String> {
+        // Indexes without a path are kept in memory only
+        if !self.dirty || self.index_path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        write_atomically(&self.index_path, to_json(self)?)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    // Where a note falls in the given order. Times are inverted so that every
+    // order sorts ascending, and the uuid breaks ties
+    fn position(summary: &NoteSummary, sort: SortBy) -> (String, Uuid) {
+        let key = match sort {
+            SortBy::Modified => format!("{:020}", u64::MAX - summary.modified_at),
+            SortBy::Created => format!("{:020}", u64::MAX - summary.created_at),
+            SortBy::Title => summary.title.to_lowercase(),
+        };
+        (key, summary.uuid)
+    }
+
+    // Up to `limit` notes following `cursor`, the position of the last note
+    // of the previous page. Cursors stay valid while notes change
+    pub fn page(
+        &self,
+        sort: SortBy,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<NotePage, String> {
+        // An empty page would end the listing without a cursor to go on from
+        if limit == 0 {
+            throw!("Page limit must be at least 1");
+        }
+        let after = match cursor {
+            Some(cursor) => match cursor.rsplit_once(':') {
+                Some((key, uuid)) => match Uuid::parse_str(uuid) {
+                    Ok(uuid) => Some((key.to_string(), uuid)),
+                    Err(_) => throw!("Invalid cursor {}", cursor),
+                },
+                None => throw!("Invalid cursor {}", cursor),
+            },
+            None => None,
+        };
+        let mut positioned: Vec<((String, Uuid), &NoteSummary)> = self
+            .summaries
+            .values()
+            .map(|summary| (Self::position(summary, sort), summary))
+            .filter(|(position, _)| match &after {
+                Some(after) => position > after,
+                None => true,
+            })
+            .collect();
+        positioned.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let has_more = positioned.len() > limit;
+        positioned.truncate(limit);
+        Ok(NotePage {
+            next_cursor: match has_more {
+                true => positioned
+                    .last()
+                    .map(|((key, uuid), _)| format!("{}:{}", key, uuid)),
+                false => None,
+            },
+            notes: positioned
+                .into_iter()
+                .map(|(_, summary)| summary.to_owned())
+                .collect(),
+            total: self.summaries.len(),
+        })
+    }
+}
+
+#[tauri::command]
+pub fn list_notes(
+    sort: Option<SortBy>,
+    cursor: Option<String>,
+    limit: Option<usize>,
+    data: State<'_, Data>,
+) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.list_notes(
+        sort.unwrap_or_default(),
+        cursor.as_deref(),
+        limit.unwrap_or(DEFAULT_PAGE_SIZE),
+    )?)
+}
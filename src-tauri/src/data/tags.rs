@@ -0,0 +1,421 @@
+use crate::core::utils::json::to_json;
+use crate::data::error::NoteError;
+use crate::data::{Data, NoteFile};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tauri::State;
+use uuid::Uuid;
+
+// Tags are compared case-insensitively and may be written with a leading `#`
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().trim_start_matches('#').trim().to_lowercase();
+    match tag.is_empty() {
+        true => None,
+        false => Some(tag),
+    }
+}
+
+// Normalize a list of tags, dropping empty ones and duplicates while keeping their order
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut result: Vec<String> = vec![];
+    for tag in tags.iter().filter_map(|t| normalize_tag(t)) {
+        if !result.contains(&tag) {
+            result.push(tag);
+        }
+    }
+    result
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+// Which notes carry each tag
+#[derive(Debug, Clone, Default)]
+pub struct TagIndex {
+    tags: BTreeMap<String, HashSet<Uuid>>,
+}
+
+impl TagIndex {
+    pub fn new_from_entries(entries: &HashMap<Uuid, NoteFile>) -> Self {
+        let mut index = Self::default();
+        for (uuid, note) in entries.iter() {
+            index.update(uuid, &note.content.tags);
+        }
+        index
+    }
+
+    pub fn update(&mut self, uuid: &Uuid, tags: &[String]) {
+        self.remove(uuid);
+        for tag in tags {
+            self.tags.entry(tag.to_owned()).or_default().insert(*uuid);
+        }
+    }
+
+    pub fn remove(&mut self, uuid: &Uuid) {
+        self.tags.retain(|_, uuids| {
+            uuids.remove(uuid);
+            !uuids.is_empty()
+        });
+    }
+
+    pub fn notes_with(&self, tag: &str) -> Vec<Uuid> {
+        match self.tags.get(tag) {
+            Some(uuids) => uuids.iter().copied().collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn counts(&self) -> Vec<TagCount> {
+        self.tags
+            .iter()
+            .map(|(tag, uuids)| TagCount {
+                tag: tag.to_owned(),
+                count: uuids.len(),
+            })
+            .collect()
+    }
+}
+
+// A parsed tag filter such as `work AND (urgent OR "follow up") AND NOT done`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagExpr {
+    Tag(String),
+    Not(Box<TagExpr>),
+    And(Box<TagExpr>, Box<TagExpr>),
+    Or(Box<TagExpr>, Box<TagExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Tag(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn lex(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '!' | '-' => {
+                chars.next();
+                tokens.push(Token::Not);
+            }
+            '&' | '|' => {
+                chars.next();
+                if chars.peek() == Some(&c) {
+                    chars.next();
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            '"' => {
+                chars.next();
+                let mut tag = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => tag.push(c),
+                        None => throw!("Unterminated quote in tag filter"),
+                    }
+                }
+                match normalize_tag(&tag) {
+                    Some(tag) => tokens.push(Token::Tag(tag)),
+                    None => throw!("Empty tag in tag filter"),
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "()\"&|".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(match word.to_uppercase().as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => match normalize_tag(&word) {
+                        Some(tag) => Token::Tag(tag),
+                        None => throw!("Unexpected `{}` in tag filter", word),
+                    },
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// Recursive descent over: or := and (OR and)*, and := not (AND? not)*, not := NOT not | atom
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<TagExpr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = TagExpr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<TagExpr, String> {
+        let mut expr = self.not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                // Adjacent terms are an implicit AND
+                Some(Token::Tag(_)) | Some(Token::Not) | Some(Token::Open) => {}
+                _ => break,
+            }
+            expr = TagExpr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<TagExpr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(TagExpr::Not(Box::new(self.not()?))),
+            Some(Token::Tag(tag)) => Ok(TagExpr::Tag(tag)),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => throw!("Missing `)` in tag filter"),
+                }
+            }
+            Some(token) => throw!("Unexpected {:?} in tag filter", token),
+            None => throw!("Tag filter ended unexpectedly"),
+        }
+    }
+}
+
+impl TagExpr {
+    pub fn parse(input: &str) -> Result<TagExpr, String> {
+        let mut parser = Parser {
+            tokens: lex(input)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            throw!("Unexpected {:?} in tag filter", token);
+        }
+        Ok(expr)
+    }
+
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            TagExpr::Tag(tag) => tags.contains(tag),
+            TagExpr::Not(expr) => !expr.matches(tags),
+            TagExpr::And(a, b) => a.matches(tags) && b.matches(tags),
+            TagExpr::Or(a, b) => a.matches(tags) || b.matches(tags),
+        }
+    }
+}
+
+#[tauri::command]
+pub fn add_tag(uuid: Uuid, tag: String, data: State<'_, Data>) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+    cache.add_tag(&uuid, &tag)?;
+
+    Ok(to_json(&cache.get(&uuid)?)?)
+}
+
+#[tauri::command]
+pub fn remove_tag(uuid: Uuid, tag: String, data: State<'_, Data>) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+    cache.remove_tag(&uuid, &tag)?;
+
+    Ok(to_json(&cache.get(&uuid)?)?)
+}
+
+#[tauri::command]
+pub fn rename_tag(from: String, to: String, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    cache.rename_tag(&from, &to)?;
+
+    to_json(&cache.list_tags())
+}
+
+#[tauri::command]
+pub fn list_tags(data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.list_tags())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str) -> TagExpr {
+        TagExpr::Tag(name.to_string())
+    }
+
+    fn not(expr: TagExpr) -> TagExpr {
+        TagExpr::Not(Box::new(expr))
+    }
+
+    fn and(a: TagExpr, b: TagExpr) -> TagExpr {
+        TagExpr::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: TagExpr, b: TagExpr) -> TagExpr {
+        TagExpr::Or(Box::new(a), Box::new(b))
+    }
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn lex_reads_keywords_symbols_and_quoted_tags() {
+        assert_eq!(
+            lex("#Work and \"Follow Up\" || !done").unwrap(),
+            vec![
+                Token::Tag("work".to_string()),
+                Token::And,
+                Token::Tag("follow up".to_string()),
+                Token::Or,
+                Token::Not,
+                Token::Tag("done".to_string()),
+            ]
+        );
+        assert_eq!(
+            lex("(a&b)|-c").unwrap(),
+            vec![
+                Token::Open,
+                Token::Tag("a".to_string()),
+                Token::And,
+                Token::Tag("b".to_string()),
+                Token::Close,
+                Token::Or,
+                Token::Not,
+                Token::Tag("c".to_string()),
+            ]
+        );
+        // Only a leading `-` negates, inside a word it is part of the tag
+        assert_eq!(
+            lex("follow-up").unwrap(),
+            vec![Token::Tag("follow-up".to_string())]
+        );
+    }
+
+    #[test]
+    fn parse_binds_not_tighter_than_and_tighter_than_or() {
+        assert_eq!(
+            TagExpr::parse("a OR b AND c").unwrap(),
+            or(tag("a"), and(tag("b"), tag("c")))
+        );
+        assert_eq!(
+            TagExpr::parse("a AND b OR c").unwrap(),
+            or(and(tag("a"), tag("b")), tag("c"))
+        );
+        assert_eq!(
+            TagExpr::parse("NOT a AND b").unwrap(),
+            and(not(tag("a")), tag("b"))
+        );
+        assert_eq!(
+            TagExpr::parse("not not a or b").unwrap(),
+            or(not(not(tag("a"))), tag("b"))
+        );
+        // Adjacent terms are an implicit AND, binding tighter than OR
+        assert_eq!(
+            TagExpr::parse("a b | c").unwrap(),
+            or(and(tag("a"), tag("b")), tag("c"))
+        );
+        // Chains group from the left
+        assert_eq!(
+            TagExpr::parse("a or b or c").unwrap(),
+            or(or(tag("a"), tag("b")), tag("c"))
+        );
+    }
+
+    #[test]
+    fn parse_follows_parentheses() {
+        assert_eq!(
+            TagExpr::parse("(a OR b) AND c").unwrap(),
+            and(or(tag("a"), tag("b")), tag("c"))
+        );
+        assert_eq!(
+            TagExpr::parse("NOT (a OR b)").unwrap(),
+            not(or(tag("a"), tag("b")))
+        );
+        assert_eq!(
+            TagExpr::parse("((a)) and (b or (c and not d))").unwrap(),
+            and(tag("a"), or(tag("b"), and(tag("c"), not(tag("d")))))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_malformed_filters() {
+        for input in [
+            "",
+            "   ",
+            "(",
+            ")",
+            "()",
+            "(a",
+            "a)",
+            "(a or b",
+            "a or",
+            "and a",
+            "a and and b",
+            "a or or b",
+            "not",
+            "a not",
+            "\"unterminated",
+            "\"\"",
+            "\"  # \"",
+            "#",
+            "a | (b &)",
+        ] {
+            assert!(TagExpr::parse(input).is_err(), "{:?} parsed", input);
+        }
+    }
+
+    #[test]
+    fn matches_evaluates_the_filter() {
+        let filter = TagExpr::parse("work AND (urgent OR \"follow up\") AND NOT done").unwrap();
+        assert!(filter.matches(&tags(&["work", "urgent"])));
+        assert!(filter.matches(&tags(&["follow up", "work"])));
+        assert!(!filter.matches(&tags(&["work", "urgent", "done"])));
+        assert!(!filter.matches(&tags(&["work"])));
+        assert!(!filter.matches(&tags(&[])));
+    }
+}
@@ -0,0 +1,165 @@
+use crate::core::utils::fs::write_atomically;
+use crate::core::utils::json::to_json;
+use crate::core::utils::time::now_millis;
+use crate::data::error::NoteError;
+use crate::data::migrations::{Envelope, CURRENT_FORMAT_VERSION};
+use crate::data::{Data, NoteFile};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::State;
+use uuid::Uuid;
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct TrashEntry {
+    pub uuid: Uuid,
+    pub file_name: String,
+    pub deleted_at: u64,
+}
+
+// Deleted notes, kept in a directory beside the data directory until purged
+#[derive(Debug)]
+pub struct Trash {
+    pub trash_path: PathBuf,
+    pub max_age: Duration,
+    entries: HashMap<Uuid, TrashEntry>,
+}
+
+impl Trash {
+    // Initialize the trash from its index file, if there is one
+    pub fn new(trash_path: &PathBuf, max_age: Duration) -> Trash {
+        let mut entries = HashMap::new();
+        if let Ok(index_str) = std::fs::read_to_string(trash_path.join("index.json")) {
+            match serde_json::from_str::<Vec<TrashEntry>>(&index_str) {
+                Ok(list) => {
+                    for entry in list {
+                        entries.insert(entry.uuid, entry);
+                    }
+                }
+                Err(e) => eprintln!("Could not parse trash index: {}", e),
+            }
+        }
+        Self {
+            trash_path: trash_path.to_path_buf(),
+            max_age,
+            entries,
+        }
+    }
+
+    fn save_index(&self) -> Result<(), String> {
+        write_atomically(&self.trash_path.join("index.json"), to_json(&self.list())?)
+    }
+
+    // Keep a copy of a note in the trash. The caller removes it from the backend
+    pub fn put(&mut self, note: &NoteFile) -> Result<(), String> {
+        let uuid = match note.uuid {
+            Some(uuid) => uuid,
+            None => throw!("Cannot trash a note without a uuid"),
+        };
+        let file_name = format!("{}.json", uuid);
+        let envelope = Envelope {
+            format_version: CURRENT_FORMAT_VERSION,
+            data: note,
+        };
+        write_atomically(&self.trash_path.join(&file_name), to_json(&envelope)?)?;
+
+        // A note rewritten while in the trash keeps its deletion time
+        let deleted_at = match self.entries.get(&uuid) {
+            Some(entry) => entry.deleted_at,
+            None => now_millis(),
+        };
+        self.entries.insert(
+            uuid,
+            TrashEntry {
+                uuid,
+                file_name,
+                deleted_at,
+            },
+        );
+        self.save_index()
+    }
+
+    // Read a trashed note back. It stays in the trash until `remove` is called
+    pub fn get(&self, uuid: &Uuid) -> Result<NoteFile, String> {
+        match self.entries.get(uuid) {
+            Some(entry) => NoteFile::load(&self.trash_path.join(&entry.file_name)),
+            None => throw!("Note {} is not in the trash", uuid),
+        }
+    }
+
+    // Permanently delete everything in the trash, returning what was removed
+    pub fn empty(&mut self) -> Result<Vec<Uuid>, String> {
+        let uuids: Vec<Uuid> = self.entries.keys().copied().collect();
+        self.remove(&uuids)
+    }
+
+    // Permanently delete notes that have been in the trash longer than `max_age`
+    pub fn purge_expired(&mut self) -> Result<Vec<Uuid>, String> {
+        let cutoff = now_millis().saturating_sub(self.max_age.as_millis() as u64);
+        let expired: Vec<Uuid> = self
+            .entries
+            .values()
+            .filter(|e| e.deleted_at < cutoff)
+            .map(|e| e.uuid)
+            .collect();
+        if expired.is_empty() {
+            return Ok(expired);
+        }
+        self.remove(&expired)
+    }
+
+    // Permanently delete notes from the trash
+    pub fn remove(&mut self, uuids: &[Uuid]) -> Result<Vec<Uuid>, String> {
+        for uuid in uuids {
+            if let Some(entry) = self.entries.remove(uuid) {
+                let path = self.trash_path.join(&entry.file_name);
+                if let Err(e) = std::fs::remove_file(&path) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        throw!("Error removing {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+        self.save_index()?;
+        Ok(uuids.to_vec())
+    }
+
+    pub fn list(&self) -> Vec<TrashEntry> {
+        let mut result: Vec<TrashEntry> = self.entries.values().map(|e| e.to_owned()).collect();
+        result.sort_by_key(|e| std::cmp::Reverse(e.deleted_at));
+        result
+    }
+}
+
+#[tauri::command]
+pub fn delete_note(uuid: Uuid, data: State<'_, Data>) -> Result<Value, NoteError> {
+    let cache = data.0.lock().unwrap();
+
+    Ok(to_json(&cache.delete(&uuid)?)?)
+}
+
+#[tauri::command]
+pub fn restore_note(uuid: Uuid, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    cache.restore(&uuid)?;
+
+    to_json(&cache.get(&uuid)?)
+}
+
+#[tauri::command]
+pub fn empty_trash(data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    cache.empty_trash()?;
+
+    to_json(&cache.get_trash())
+}
+
+#[tauri::command]
+pub fn get_trash(data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.get_trash())
+}
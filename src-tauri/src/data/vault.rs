@@ -0,0 +1,363 @@
+use crate::core::utils::fs::write_atomically;
+use crate::core::utils::json::to_json;
+use crate::data::{Data, NoteFile};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tauri::State;
+use zeroize::Zeroizing;
+
+const VAULT_VERSION: u32 = 1;
+const VAULT_FILE: &str = "vault.json";
+const KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+
+// Data encrypted with XChaCha20-Poly1305, base64 encoded
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct Sealed {
+    pub nonce: String,
+    pub data: String,
+}
+
+// A whole file sealed by the vault
+#[derive(Serialize, Deserialize)]
+struct SealedFile {
+    vault: Sealed,
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+    match base64::decode(value) {
+        Ok(bytes) => Ok(bytes),
+        Err(e) => throw!("Could not decode encrypted data: {}", e),
+    }
+}
+
+#[derive(Clone)]
+pub struct Cipher(XChaCha20Poly1305);
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cipher")
+    }
+}
+
+impl Cipher {
+    pub fn new(key: &[u8]) -> Self {
+        Self(XChaCha20Poly1305::new(Key::from_slice(key)))
+    }
+
+    // Encrypt with a fresh random nonce
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Sealed, String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        match self.0.encrypt(&nonce, plaintext) {
+            Ok(data) => Ok(Sealed {
+                nonce: base64::encode(nonce),
+                data: base64::encode(data),
+            }),
+            Err(_) => throw!("Could not encrypt data"),
+        }
+    }
+
+    pub fn open(&self, sealed: &Sealed) -> Result<Vec<u8>, String> {
+        let nonce = decode(&sealed.nonce)?;
+        if nonce.len() != NONCE_LENGTH {
+            throw!("Could not decrypt data: invalid nonce");
+        }
+        match self
+            .0
+            .decrypt(XNonce::from_slice(&nonce), decode(&sealed.data)?.as_ref())
+        {
+            Ok(plaintext) => Ok(plaintext),
+            Err(_) => throw!("Could not decrypt data: wrong key or corrupted data"),
+        }
+    }
+}
+
+// Argon2id parameters for turning a passphrase into a key
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct Kdf {
+    pub salt: String,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Kdf {
+    // A random salt with the recommended cost (19 MiB, 2 passes)
+    pub fn generate() -> Self {
+        let mut salt = [0u8; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            salt: base64::encode(salt),
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+
+    pub fn derive(&self, passphrase: &str) -> Result<Cipher, String> {
+        let params = match Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_LENGTH)) {
+            Ok(params) => params,
+            Err(e) => throw!("Invalid key derivation parameters: {}", e),
+        };
+        let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+        let result = Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(
+            passphrase.as_bytes(),
+            &decode(&self.salt)?,
+            &mut *key,
+        );
+        if let Err(e) = result {
+            throw!("Could not derive key: {}", e);
+        }
+        Ok(Cipher::new(&*key))
+    }
+}
+
+// Kept in `vault.json`. Notes are encrypted with a random data key, which is
+// stored sealed with the passphrase key so the passphrase can change without
+// re-encrypting every note
+#[derive(Serialize, Debug, Deserialize, Clone)]
+struct VaultHeader {
+    version: u32,
+    kdf: Kdf,
+    wrapped_key: Sealed,
+}
+
+impl VaultHeader {
+    fn wrap(passphrase: &str, data_key: &[u8]) -> Result<Self, String> {
+        let kdf = Kdf::generate();
+        let wrapped_key = kdf.derive(passphrase)?.seal(data_key)?;
+        Ok(Self {
+            version: VAULT_VERSION,
+            kdf,
+            wrapped_key,
+        })
+    }
+
+    fn data_key(&self, passphrase: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+        match self.kdf.derive(passphrase)?.open(&self.wrapped_key) {
+            Ok(data_key) => Ok(Zeroizing::new(data_key)),
+            Err(_) => throw!("Wrong passphrase"),
+        }
+    }
+}
+
+// The unlocked data key, shared with everything that writes note data to disk.
+// When the vault is disabled data passes through untouched
+#[derive(Debug, Clone, Default)]
+pub struct VaultKey {
+    enabled: bool,
+    cipher: Arc<RwLock<Option<Cipher>>>,
+}
+
+impl VaultKey {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.enabled && self.cipher.read().unwrap().is_none()
+    }
+
+    fn set(&self, cipher: Option<Cipher>) {
+        *self.cipher.write().unwrap() = cipher;
+    }
+
+    // The cipher to seal data with, or `None` when the vault is disabled
+    pub fn cipher(&self) -> Result<Option<Cipher>, String> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        match self.cipher.read().unwrap().clone() {
+            Some(cipher) => Ok(Some(cipher)),
+            None => throw!("The vault is locked"),
+        }
+    }
+
+    // Wrap a JSON file's contents before it is written
+    pub fn seal_json(&self, value: Value) -> Result<Value, String> {
+        match self.cipher()? {
+            Some(cipher) => to_json(&SealedFile {
+                vault: cipher.seal(value.to_string().as_bytes())?,
+            }),
+            None => Ok(value),
+        }
+    }
+
+    // Unwrap a JSON file read from disk. Files written before the vault existed are returned as is
+    pub fn open_json(&self, value: Value) -> Result<Value, String> {
+        let sealed = match serde_json::from_value::<SealedFile>(value.clone()) {
+            Ok(sealed) => sealed.vault,
+            Err(_) => return Ok(value),
+        };
+        let cipher = match self.cipher.read().unwrap().clone() {
+            Some(cipher) => cipher,
+            None => throw!("The vault is locked"),
+        };
+        match serde_json::from_slice(&cipher.open(&sealed)?) {
+            Ok(value) => Ok(value),
+            Err(e) => throw!("Could not parse decrypted data: {}", e),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Unlocked {
+    pub notes: Vec<NoteFile>,
+    // What creating the vault had to delete, shown to the user
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct VaultStatus {
+    pub enabled: bool,
+    pub created: bool,
+    pub unlocked: bool,
+}
+
+#[derive(Debug)]
+pub struct Vault {
+    pub vault_path: PathBuf,
+    header: Option<VaultHeader>,
+    key: VaultKey,
+}
+
+impl Vault {
+    pub fn new(app_dir: &PathBuf, enabled: bool) -> Self {
+        let vault_path = app_dir.join(VAULT_FILE);
+        let header = match std::fs::read_to_string(&vault_path) {
+            Ok(header_str) => match serde_json::from_str(&header_str) {
+                Ok(header) => Some(header),
+                Err(e) => {
+                    eprintln!("Could not parse vault file: {}", e);
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+        Self {
+            vault_path,
+            header,
+            key: VaultKey {
+                enabled,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn key(&self) -> VaultKey {
+        self.key.clone()
+    }
+
+    pub fn status(&self) -> VaultStatus {
+        VaultStatus {
+            enabled: self.key.enabled,
+            created: self.header.is_some(),
+            unlocked: self.key.enabled && !self.key.is_locked(),
+        }
+    }
+
+    // Unlock with the passphrase, creating the vault on first use. Returns whether it was created
+    pub fn unlock(&mut self, passphrase: &str) -> Result<bool, String> {
+        if !self.key.enabled {
+            throw!("Encryption is not enabled in the settings");
+        }
+        if let Some(header) = &self.header {
+            let data_key = header.data_key(passphrase)?;
+            self.key.set(Some(Cipher::new(&data_key)));
+            return Ok(false);
+        }
+
+        // Never replace a vault file we failed to read, the notes' key is in it
+        if self.vault_path.exists() {
+            throw!("Could not read {}", self.vault_path.display());
+        }
+        if passphrase.is_empty() {
+            throw!("The passphrase cannot be empty");
+        }
+        let mut data_key = Zeroizing::new([0u8; KEY_LENGTH]);
+        OsRng.fill_bytes(&mut *data_key);
+        let header = VaultHeader::wrap(passphrase, &*data_key)?;
+        write_atomically(&self.vault_path, to_json(&header)?)?;
+        self.header = Some(header);
+        self.key.set(Some(Cipher::new(&*data_key)));
+
+        // The persisted indexes hold the words and excerpts of every note in plaintext
+        for index in ["search-index.json", "summary-index.json"] {
+            let index_path = self.vault_path.with_file_name(index);
+            if let Err(e) = std::fs::remove_file(&index_path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    eprintln!("Error removing {}: {}", index_path.display(), e);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn lock(&mut self) -> Result<(), String> {
+        if !self.key.enabled {
+            throw!("Encryption is not enabled in the settings");
+        }
+        self.key.set(None);
+        Ok(())
+    }
+
+    pub fn change_passphrase(&mut self, old: &str, new: &str) -> Result<(), String> {
+        let header = match &self.header {
+            Some(header) => header,
+            None => throw!("The vault has not been created yet"),
+        };
+        if new.is_empty() {
+            throw!("The passphrase cannot be empty");
+        }
+        let header = VaultHeader::wrap(new, &header.data_key(old)?)?;
+        write_atomically(&self.vault_path, to_json(&header)?)?;
+        self.header = Some(header);
+        Ok(())
+    }
+}
+
+#[tauri::command]
+pub fn unlock_vault(passphrase: String, data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    let warnings = cache.unlock_vault(&passphrase)?;
+
+    to_json(&Unlocked {
+        notes: cache.get_headers(),
+        warnings,
+    })
+}
+
+#[tauri::command]
+pub fn lock_vault(data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    cache.lock_vault()?;
+
+    to_json(&cache.vault_status())
+}
+
+#[tauri::command]
+pub fn change_passphrase(
+    old_passphrase: String,
+    new_passphrase: String,
+    data: State<'_, Data>,
+) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+    cache.change_passphrase(&old_passphrase, &new_passphrase)?;
+
+    to_json(&cache.vault_status())
+}
+
+#[tauri::command]
+pub fn get_vault_status(data: State<'_, Data>) -> Result<Value, String> {
+    let cache = data.0.lock().unwrap();
+
+    to_json(&cache.vault_status())
+}
@@ -0,0 +1,120 @@
+use crate::data::Data;
+
+use notify::{recommended_watcher, Event, EventKind, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use uuid::Uuid;
+
+// Changes to a file usually come as several events; wait this long for the rest
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+// Payload of the `notes-changed` event
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+pub struct NotesChanged {
+    pub changed: Vec<Uuid>,
+    pub removed: Vec<Uuid>,
+}
+
+impl NotesChanged {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+// Call `on_change` from a background thread with the paths in `dir` that
+// changed, a burst of changes at a time
+pub fn watch<F>(dir: PathBuf, mut on_change: F) -> Result<(), String>
+where
+    F: FnMut(Vec<PathBuf>) + Send + 'static,
+{
+    let (sender, receiver) = channel();
+    let mut watcher = match recommended_watcher(sender) {
+        Ok(watcher) => watcher,
+        Err(e) => throw!("Could not watch {}: {}", dir.display(), e),
+    };
+    if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+        throw!("Could not watch {}: {}", dir.display(), e);
+    }
+
+    std::thread::spawn(move || {
+        let mut paths = HashSet::new();
+        let mut replaced = false;
+        let mut timeout = None;
+        loop {
+            // Block until something changes, then gather events until they settle
+            let received = match timeout {
+                Some(timeout) => receiver.recv_timeout(timeout),
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let event: Event = match received {
+                Ok(Ok(event)) => event,
+                Ok(Err(e)) => {
+                    eprintln!("Error watching {}: {}", dir.display(), e);
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    // Restoring a backup moves the whole directory away, and
+                    // the watch with it, so watch the one now in its place
+                    if replaced {
+                        let _ = watcher.unwatch(&dir);
+                        if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                            eprintln!("Could not watch {}: {}", dir.display(), e);
+                        }
+                        replaced = false;
+                    }
+                    on_change(paths.drain().collect());
+                    timeout = None;
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            if let EventKind::Access(_) = event.kind {
+                continue;
+            }
+            for path in event.paths {
+                match path == dir {
+                    true => replaced = true,
+                    false => {
+                        paths.insert(path);
+                    }
+                }
+            }
+            timeout = Some(SETTLE_TIME);
+        }
+    });
+    Ok(())
+}
+
+// Reload notes edited outside the app, e.g. by a sync client, and tell the
+// windows about them
+pub fn start_watcher(app: AppHandle) {
+    let dir = {
+        let data = app.state::<Data>();
+        let cache = data.0.lock().unwrap();
+        cache.watch_dir()
+    };
+    let dir = match dir {
+        Some(dir) => dir,
+        None => return,
+    };
+    let result = watch(dir, move |paths| {
+        let changes = {
+            let data = app.state::<Data>();
+            let cache = data.0.lock().unwrap();
+            cache.reload_paths(&paths)
+        };
+        if changes.is_empty() {
+            return;
+        }
+        if let Err(e) = app.emit_all("notes-changed", changes) {
+            eprintln!("Error sending notes-changed: {}", e);
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("{}", e);
+    }
+}
@@ -15,6 +15,16 @@ mod data;
 
 use std::sync::Mutex;
 
+use tauri::{Manager, RunEvent};
+
+use data::backend::files::FileBackend;
+use data::backend::memory::MemoryBackend;
+use data::backend::sqlite::{SqliteBackend, DATABASE_FILE};
+use data::backend::vault::VaultBackend;
+use data::backend::Backend;
+use data::backups::BackupReason;
+use data::settings::{Settings, StorageBackend, StorageFormat};
+use data::vault::Vault;
 use data::{AppData, Data, Store};
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
@@ -26,19 +36,99 @@ fn main() {
     let ctx = tauri::generate_context!();
 
     let paths = AppData::initialize_from_config(ctx.config());
-    let store = Store::new(paths);
+    let settings = Settings::load(&paths.app_dir);
+    let vault = Vault::new(&paths.app_dir, settings.vault);
+    // Encrypted notes have no readable title to name a markdown file after
+    let storage_format = match settings.vault {
+        true => StorageFormat::Json,
+        false => settings.storage_format,
+    };
+    let backend: Box<dyn Backend> = match settings.storage_backend {
+        StorageBackend::Files => Box::new(FileBackend::new(&paths.data_dir, storage_format)),
+        StorageBackend::Sqlite => {
+            let mut backend = SqliteBackend::open(&paths.app_dir.join(DATABASE_FILE))
+                .expect("error while opening the notes database");
+            match backend.import_data_dir(&paths.data_dir) {
+                Ok(0) => {}
+                Ok(count) => eprintln!("Imported {} notes into the notes database", count),
+                Err(e) => eprintln!("Error importing notes into the notes database: {}", e),
+            }
+            Box::new(backend)
+        }
+        StorageBackend::Memory => Box::new(MemoryBackend::new()),
+    };
+    let backend: Box<dyn Backend> = match settings.vault {
+        true => Box::new(VaultBackend::new(backend, vault.key())),
+        false => backend,
+    };
+    let store = Store::new(paths, &settings, vault, backend);
 
     let app = tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![
             greet,
             data::save_file,
-            data::get_files
+            data::get_files,
+            data::get_note,
+            data::get_notes,
+            data::summary::list_notes,
+            data::trash::delete_note,
+            data::trash::restore_note,
+            data::trash::empty_trash,
+            data::trash::get_trash,
+            data::migrations::get_migration_report,
+            data::search::search_notes,
+            data::revisions::list_revisions,
+            data::revisions::get_revision,
+            data::revisions::diff_revisions,
+            data::revisions::restore_revision,
+            data::tags::add_tag,
+            data::tags::remove_tag,
+            data::tags::rename_tag,
+            data::tags::list_tags,
+            data::notebooks::create_notebook,
+            data::notebooks::rename_notebook,
+            data::notebooks::move_notebook,
+            data::notebooks::delete_notebook,
+            data::notebooks::move_note,
+            data::notebooks::get_notebook_tree,
+            data::vault::unlock_vault,
+            data::vault::lock_vault,
+            data::vault::change_passphrase,
+            data::vault::get_vault_status,
+            data::locked::lock_note,
+            data::locked::unlock_note,
+            data::links::get_backlinks,
+            data::links::get_outgoing_links,
+            data::graph::export_graph,
+            data::render::render_note,
+            data::render::get_highlight_css,
+            data::site::export_site,
+            data::import::obsidian::import_obsidian,
+            data::import::enex::import_enex,
+            data::archive::export_archive,
+            data::archive::import_archive,
+            data::backups::list_backups,
+            data::backups::create_backup,
+            data::backups::restore_backup
         ])
         .manage(Data(Mutex::new(store)))
         .build(ctx)
         .expect("error while running tauri application");
 
-    app.run(|_app_handle, e| match e {
-        _ => {}
+    data::events::emit_to_windows(app.handle());
+    data::backups::start_worker(app.handle());
+    data::watcher::start_watcher(app.handle());
+
+    app.run(|app_handle, e| {
+        if let RunEvent::Exit = e {
+            let data = app_handle.state::<Data>();
+            let cache = data.0.lock().unwrap();
+            cache.persist();
+            if cache.backups_enabled() {
+                if let Err(e) = cache.backup(BackupReason::Exit) {
+                    eprintln!("Error backing up notes: {}", e);
+                }
+            }
+        }
     });
 }